@@ -0,0 +1,23 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use oimdp_rs::{parser, parser_with_options, ParseOptions};
+
+// Parse the main test file from start to finish. Run with `cargo bench`
+fn parse_test_file(c: &mut Criterion) {
+    let full_text = std::fs::read_to_string("test.md").unwrap();
+
+    c.bench_function("parse test.md", |b| {
+        b.iter(|| parser(black_box(&full_text)).unwrap());
+    });
+
+    let options = ParseOptions {
+        parallel: true,
+        ..Default::default()
+    };
+
+    c.bench_function("parse test.md in parallel", |b| {
+        b.iter(|| parser_with_options(black_box(&full_text), options).unwrap());
+    });
+}
+
+criterion_group!(benches, parse_test_file);
+criterion_main!(benches);
@@ -0,0 +1,191 @@
+use crate::structures::*;
+use std::ops::Range;
+
+// Logical blocks: a marker (paragraph, biography, dictionary entry, etc.) together with the
+// lines and page breaks that follow it, up to the next marker. The parser emits all of these
+// as separate, flat Content items, which makes it awkward to treat, e.g., one biography as
+// one record
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BlockKind {
+    Paragraph,
+    Riwayat,
+    Verse,
+    Route,
+    Biography(BeType),
+    Dictionary(DicType),
+    Doxography(DoxType),
+    // Lines with no marker of their own, e.g., right after a header
+    Loose,
+    // Items that aren't made of lines: headers, editorial notes, unknown lines, etc.
+    Other,
+}
+
+#[derive(Clone, Debug)]
+pub struct Block<'a> {
+    pub kind: BlockKind,
+    // Indices of the block's items in the document's content
+    pub range: Range<usize>,
+    // The item that opened the block, unless it opened with a line
+    pub marker: Option<&'a Content<'a>>,
+    pub lines: Vec<&'a Line<'a>>,
+    // Page numbers, whether on lines of their own or inline, in order
+    pub page_breaks: Vec<&'a PageNumber<'a>>,
+}
+
+impl Block<'_> {
+    /// Plain text of the block, line by line, joined with spaces
+    #[must_use]
+    pub fn text(&self) -> String {
+        let lines: Vec<&str> = self
+            .lines
+            .iter()
+            .filter_map(|line| line.text_only.as_deref())
+            .collect();
+
+        lines.join(" ")
+    }
+}
+
+pub struct Blocks<'a> {
+    content: &'a [Content<'a>],
+    index: usize,
+}
+
+// What kind of block an item opens, if any. Normal lines and page numbers only continue one
+const fn opens_block(item: &Content) -> Option<BlockKind> {
+    match item {
+        Content::Paragraph { para_type, .. } => match para_type {
+            ParaType::Normal => Some(BlockKind::Paragraph),
+            ParaType::Riwayat => Some(BlockKind::Riwayat),
+        },
+        Content::BioOrEvent { be_type, .. } => Some(BlockKind::Biography(*be_type)),
+        Content::DictionaryUnit { dic_type, .. } => Some(BlockKind::Dictionary(*dic_type)),
+        Content::DoxographicalItem { dox_type, .. } => Some(BlockKind::Doxography(*dox_type)),
+        Content::Line(Line { line_type, .. }) => match line_type {
+            LineType::Verse => Some(BlockKind::Verse),
+            LineType::RouteOrDistance => Some(BlockKind::Route),
+            LineType::Normal => None,
+        },
+        Content::PageNumber(_) => None,
+        _ => Some(BlockKind::Other),
+    }
+}
+
+impl<'a> Iterator for Blocks<'a> {
+    type Item = Block<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let start = self.index;
+        let first = self.content.get(start)?;
+
+        let kind = opens_block(first).unwrap_or(BlockKind::Loose);
+
+        let mut block = Block {
+            marker: None,
+            lines: Vec::new(),
+            page_breaks: Vec::new(),
+            range: start..start,
+            kind,
+        };
+
+        for (i, item) in self.content.iter().enumerate().skip(start) {
+            // Anything that opens a block ends the current one (but the first item is ours)
+            if i > start && opens_block(item).is_some() {
+                break;
+            }
+
+            match item {
+                Content::Line(line) => {
+                    block.lines.push(line);
+
+                    for part in &line.parts {
+                        if let LinePart::PageNumber(page_number) = part {
+                            block.page_breaks.push(page_number);
+                        }
+                    }
+                }
+                Content::PageNumber(page_number) => block.page_breaks.push(page_number),
+                _ => block.marker = Some(item),
+            }
+
+            block.range.end = i + 1;
+
+            // Headers and the like stand alone
+            if block.kind == BlockKind::Other {
+                break;
+            }
+        }
+
+        self.index = block.range.end;
+
+        Some(block)
+    }
+}
+
+impl Document<'_> {
+    #[must_use]
+    pub fn blocks(&self) -> Blocks<'_> {
+        Blocks {
+            content: &self.content,
+            index: 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser;
+
+    #[test]
+    fn biography() {
+        let full_text = std::fs::read_to_string("test.md").unwrap();
+        let doc = parser(&full_text).unwrap();
+        let blocks: Vec<Block> = doc.blocks().collect();
+
+        // The first paragraph is only a page number; then comes a biography over four lines
+        // plus a page break
+        assert_eq!(blocks[0].kind, BlockKind::Paragraph);
+        assert_eq!(blocks[0].page_breaks[0].page, "000");
+
+        let bio = &blocks[1];
+        assert_eq!(bio.kind, BlockKind::Biography(BeType::Man));
+        assert_eq!(bio.lines.len(), 4);
+        assert_eq!(bio.page_breaks.len(), 1);
+        assert_eq!(bio.page_breaks[0].page, "030");
+        assert!(bio
+            .text()
+            .starts_with("أبو عمرو ابن العلاء واسمه زبان بن العلاء"));
+
+        // Blocks cover all the content, in order, without overlapping
+        let mut end = 0;
+        for block in &blocks {
+            assert_eq!(block.range.start, end);
+            end = block.range.end;
+        }
+        assert_eq!(end, doc.content.len());
+    }
+
+    #[test]
+    fn kinds() {
+        let input = "######OpenITI#\n~~loose\n### | header\n# $RWY$ isnad\n~~more\n# verse %~% verse\n~~more\n### $DIC_NIS$ entry";
+        let doc = parser(input).unwrap();
+
+        let kinds: Vec<(BlockKind, usize)> = doc
+            .blocks()
+            .map(|block| (block.kind, block.lines.len()))
+            .collect();
+
+        assert_eq!(
+            kinds,
+            [
+                (BlockKind::Loose, 1),
+                (BlockKind::Other, 0),
+                (BlockKind::Riwayat, 2),
+                (BlockKind::Verse, 2),
+                (BlockKind::Dictionary(DicType::Nis), 1),
+            ]
+        );
+    }
+}
@@ -0,0 +1,357 @@
+use crate::lexer::{Lexer, Tag};
+use crate::{structures::*, tokens::is_arabic_token_char};
+use std::io;
+
+// CoNLL export
+// Named-entity tags turned into training data for NER models: one token per line, with its
+// label, and a blank line between sentences (or milestone units). Entity prefixes (و, ب, ال,
+// etc.) become tokens of their own, outside the entity
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TagScheme {
+    // B-PER for the first token of an entity, I-PER for the rest, O outside
+    #[default]
+    Bio,
+    // Same, but with E-PER for the last token and S-PER for an entity of one token
+    Bioes,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ConllSplit {
+    // At sentence-final punctuation, and wherever a paragraph, header, etc. starts
+    #[default]
+    Sentence,
+    Milestone,
+}
+
+// What to call each type of entity in the labels
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EntityLabels {
+    pub per: String,
+    pub top: String,
+    pub soc: String,
+    pub src: String,
+}
+
+impl Default for EntityLabels {
+    fn default() -> Self {
+        Self {
+            per: "PER".into(),
+            top: "TOP".into(),
+            soc: "SOC".into(),
+            src: "SRC".into(),
+        }
+    }
+}
+
+impl EntityLabels {
+    #[must_use]
+    pub fn get(&self, ne_type: EntityType) -> &str {
+        match ne_type {
+            EntityType::Per => &self.per,
+            EntityType::Top => &self.top,
+            EntityType::Soc => &self.soc,
+            EntityType::Src => &self.src,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ConllOptions {
+    pub scheme: TagScheme,
+    pub split: ConllSplit,
+    pub labels: EntityLabels,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConllToken {
+    pub text: String,
+    // E.g., B-PER, or O
+    pub label: String,
+}
+
+/// Split text into tokens for CoNLL: runs of Arabic letters (see [`is_arabic_token_char`])
+/// or other letters and digits, and every other character apart from whitespace on its own
+pub fn conll_tokens(text: &str) -> impl Iterator<Item = &str> {
+    let mut rest = text;
+
+    std::iter::from_fn(move || {
+        rest = rest.trim_start();
+        let first = rest.chars().next()?;
+
+        let len = if is_word_char(first) {
+            rest.find(|c| !is_word_char(c)).unwrap_or(rest.len())
+        } else {
+            first.len_utf8()
+        };
+
+        let (token, remainder) = rest.split_at(len);
+        rest = remainder;
+        Some(token)
+    })
+}
+
+fn is_word_char(c: char) -> bool {
+    is_arabic_token_char(c) || c.is_alphanumeric()
+}
+
+fn starts_word(token: &str) -> bool {
+    token.starts_with(is_word_char)
+}
+
+// Including the Arabic question mark and full stop
+fn ends_sentence(token: &str) -> bool {
+    matches!(token, "." | "!" | "?" | "؟" | "۔")
+}
+
+// Tokens of the segment being built, each with the entity it's part of, if any (numbered
+// by tag, so that two entities side by side stay apart)
+struct Segmenter<'o> {
+    options: &'o ConllOptions,
+    segments: Vec<Vec<ConllToken>>,
+    tokens: Vec<(String, Option<(usize, EntityType)>)>,
+    entity: usize,
+}
+
+impl Segmenter<'_> {
+    fn push(&mut self, text: &str, entity: Option<EntityType>) {
+        let tokens: Vec<&str> = conll_tokens(text).collect();
+
+        // Punctuation stuck to either end of an entity's words isn't part of it
+        let words = tokens
+            .iter()
+            .position(|token| starts_word(token))
+            .map_or(0..0, |first| {
+                first
+                    ..tokens
+                        .iter()
+                        .rposition(|token| starts_word(token))
+                        .unwrap_or(first)
+                        + 1
+            });
+
+        for (i, token) in tokens.into_iter().enumerate() {
+            let entity = entity
+                .filter(|_| words.contains(&i))
+                .map(|ne_type| (self.entity, ne_type));
+
+            self.tokens.push((token.into(), entity));
+
+            if entity.is_none()
+                && self.options.split == ConllSplit::Sentence
+                && ends_sentence(token)
+            {
+                self.close();
+            }
+        }
+    }
+
+    // Labels can only be worked out once we know where each entity ends
+    fn close(&mut self) {
+        if self.tokens.is_empty() {
+            return;
+        }
+
+        let tokens = std::mem::take(&mut self.tokens);
+        let mut segment = Vec::with_capacity(tokens.len());
+
+        for (i, (text, entity)) in tokens.iter().enumerate() {
+            let label = match entity {
+                None => "O".into(),
+                Some((id, ne_type)) => {
+                    let same = |other: Option<&(String, Option<(usize, EntityType)>)>| {
+                        other.and_then(|(_, other)| *other).map(|(other, _)| other) == Some(*id)
+                    };
+                    let first = i == 0 || !same(tokens.get(i - 1));
+                    let last = !same(tokens.get(i + 1));
+
+                    let position = match (self.options.scheme, first, last) {
+                        (TagScheme::Bioes, true, true) => "S",
+                        (TagScheme::Bioes, false, true) => "E",
+                        (_, true, _) => "B",
+                        _ => "I",
+                    };
+
+                    format!("{position}-{}", self.options.labels.get(*ne_type))
+                }
+            };
+
+            segment.push(ConllToken {
+                text: text.clone(),
+                label,
+            });
+        }
+
+        self.segments.push(segment);
+    }
+}
+
+impl Document<'_> {
+    /// The text of every line, tokenized (see [`conll_tokens`]) and labeled for named
+    /// entities, in segments split as the options say
+    #[must_use]
+    pub fn conll_segments(&self, options: &ConllOptions) -> Vec<Vec<ConllToken>> {
+        let mut segmenter = Segmenter {
+            options,
+            segments: Vec::new(),
+            tokens: Vec::new(),
+            entity: 0,
+        };
+
+        for item in &self.content {
+            match item {
+                Content::Line(line) => {
+                    for part in &line.parts {
+                        match part {
+                            LinePart::TextPart { text, .. } => segmenter.push(text, None),
+                            LinePart::NamedEntity { .. } => segmenter.entity += 1,
+                            LinePart::NamedEntityText {
+                                prefix,
+                                text,
+                                ne_type,
+                                ..
+                            } => {
+                                segmenter.push(prefix, None);
+                                segmenter.push(text, Some(*ne_type));
+                            }
+                            LinePart::Milestone { .. }
+                                if options.split == ConllSplit::Milestone =>
+                            {
+                                segmenter.close();
+                            }
+                            _ => {}
+                        }
+                    }
+                }
+                Content::PageNumber(_) => {}
+                // Headers aren't lines, so their text is left out, but they can still have
+                // milestones in them
+                Content::SectionHeader { orig, .. } if options.split == ConllSplit::Milestone => {
+                    for token in Lexer::new(orig) {
+                        if let Some(Tag::Milestone(_)) = token.tag {
+                            segmenter.close();
+                        }
+                    }
+                }
+                _ => {
+                    if options.split == ConllSplit::Sentence {
+                        segmenter.close();
+                    }
+                }
+            }
+        }
+
+        segmenter.close();
+        segmenter.segments
+    }
+
+    /// Write the document out in CoNLL format: a token and its label on each line,
+    /// separated by a tab, and a blank line after each segment
+    ///
+    /// # Errors
+    ///
+    /// Will return an error if the writer does.
+    pub fn write_conll<W: io::Write>(&self, options: &ConllOptions, mut out: W) -> io::Result<()> {
+        for segment in self.conll_segments(options) {
+            for token in segment {
+                writeln!(out, "{}\t{}", token.text, token.label)?;
+            }
+
+            writeln!(out)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser;
+
+    fn labels(segment: &[ConllToken]) -> Vec<(&str, &str)> {
+        segment
+            .iter()
+            .map(|token| (token.text.as_str(), token.label.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn tokens() {
+        let tokens: Vec<&str> = conll_tokens("قالَ، ابنُ (213) x-y؟").collect();
+        assert_eq!(
+            tokens,
+            ["قالَ", "،", "ابنُ", "(", "213", ")", "x", "-", "y", "؟"]
+        );
+    }
+
+    #[test]
+    fn export() {
+        let input = "######OpenITI#\n#META#Header#End#\n# حدثنا @PER03 محمد بن\n~~إسحاق عن @TOP11 وبغداد. ثم Milestone300 قال @SOC01 الحنفية\n# @PER01 زيد قال";
+        let doc = parser(input).unwrap();
+
+        let segments = doc.conll_segments(&ConllOptions::default());
+        assert_eq!(segments.len(), 3);
+        assert_eq!(
+            labels(&segments[0]),
+            [
+                ("حدثنا", "O"),
+                ("محمد", "B-PER"),
+                ("بن", "I-PER"),
+                ("إسحاق", "I-PER"),
+                ("عن", "O"),
+                ("و", "O"),
+                ("بغداد", "B-TOP"),
+                (".", "O"),
+            ]
+        );
+        assert_eq!(labels(&segments[2]), [("زيد", "B-PER"), ("قال", "O")]);
+
+        let options = ConllOptions {
+            scheme: TagScheme::Bioes,
+            split: ConllSplit::Milestone,
+            labels: EntityLabels {
+                top: "LOC".into(),
+                ..EntityLabels::default()
+            },
+        };
+        let segments = doc.conll_segments(&options);
+        assert_eq!(segments.len(), 2);
+
+        let found: Vec<&str> = segments[0]
+            .iter()
+            .map(|token| token.label.as_str())
+            .filter(|label| *label != "O")
+            .collect();
+        assert_eq!(found, ["B-PER", "I-PER", "E-PER", "S-LOC"]);
+        assert_eq!(segments[1][0].text, "قال");
+        assert_eq!(segments[1][1].label, "S-SOC");
+
+        let mut out = Vec::new();
+        doc.write_conll(&ConllOptions::default(), &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.starts_with("حدثنا\tO\nمحمد\tB-PER\n"));
+        assert!(out.ends_with("زيد\tB-PER\nقال\tO\n\n"));
+    }
+
+    #[test]
+    fn whole_file() {
+        let full_text = std::fs::read_to_string("test.md").unwrap();
+        let doc = parser(&full_text).unwrap();
+
+        let segments = doc.conll_segments(&ConllOptions {
+            split: ConllSplit::Milestone,
+            ..ConllOptions::default()
+        });
+        assert_eq!(segments.len(), doc.milestones().len());
+
+        // Every I- follows a B- or I- of the same type
+        for segment in doc.conll_segments(&ConllOptions::default()) {
+            for pair in segment.windows(2) {
+                if let Some(ne_type) = pair[1].label.strip_prefix("I-") {
+                    assert!(pair[0].label.ends_with(ne_type) && pair[0].label != "O");
+                }
+            }
+        }
+    }
+}
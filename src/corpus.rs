@@ -0,0 +1,298 @@
+use crate::{
+    error::ParseError, parallel::parse_files, stream::parse_file, structures::*, uri::Uri,
+};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+// Corpus
+// OpenITI keeps its texts in a fixed layout: an author folder (`0213IbnHisham`), holding one
+// folder per book (`0213IbnHisham.SiraNabawiyya`), holding one file per version
+// (`0213IbnHisham.SiraNabawiyya.Shamela0023833-ara1`), alongside the YAML sidecars. A version
+// can exist in several files at once, told apart by extension, which says how far along the
+// markup is
+
+// Ordered from most to least preferred
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum TextStatus {
+    Markdown,
+    Completed,
+    InProgress,
+    Raw,
+}
+
+impl TextStatus {
+    #[must_use]
+    pub fn from_extension(ext: Option<&str>) -> Option<Self> {
+        match ext {
+            Some("mARkdown") => Some(Self::Markdown),
+            Some("completed") => Some(Self::Completed),
+            Some("inProgress") => Some(Self::InProgress),
+            None => Some(Self::Raw),
+            _ => None,
+        }
+    }
+}
+
+// One version of a book, as found in the corpus
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CorpusText {
+    pub uri: Uri,
+    pub status: TextStatus,
+    pub path: PathBuf,
+}
+
+impl CorpusText {
+    /// # Errors
+    ///
+    /// Same as [`parse_file`].
+    pub fn parse(&self) -> Result<Document<'static>, ParseError> {
+        parse_file(&self.path)
+    }
+}
+
+// Author folders start with the four-digit death date, and that's all the dots there are.
+// That's not enough to go on, though: the repositories they're kept in are named like
+// `0025AH`. An author folder is one that has book folders in it
+fn is_author_folder(name: &str) -> bool {
+    name.len() > 4 && name.as_bytes()[..4].iter().all(u8::is_ascii_digit) && !name.contains('.')
+}
+
+fn sorted_entries(dir: &Path) -> io::Result<Vec<fs::DirEntry>> {
+    let mut entries = fs::read_dir(dir)?.collect::<io::Result<Vec<_>>>()?;
+    entries.sort_by_key(fs::DirEntry::file_name);
+
+    Ok(entries)
+}
+
+// The versions in one book folder, keeping only the preferred file for each
+fn book_texts(author: &str, book: &str, dir: &Path, texts: &mut Vec<CorpusText>) -> io::Result<()> {
+    let prefix = format!("{author}.{book}.");
+    let first = texts.len();
+
+    for entry in sorted_entries(dir)? {
+        if !entry.file_type()?.is_file() {
+            continue;
+        }
+
+        let name = entry.file_name();
+        let Some(rest) = name.to_str().and_then(|name| name.strip_prefix(&prefix)) else {
+            continue;
+        };
+
+        // Anything with an extension we don't know (the .yml sidecars, mostly) is skipped
+        let (version, ext) = match rest.split_once('.') {
+            Some((version, ext)) => (version, Some(ext)),
+            None => (rest, None),
+        };
+        let Some(status) = TextStatus::from_extension(ext) else {
+            continue;
+        };
+        // Files whose names aren't valid URIs aren't texts (this also leaves out the book's
+        // own sidecar, `0213IbnHisham.SiraNabawiyya.yml`)
+        let Ok(uri) = Uri::from_str(&format!("{prefix}{version}")) else {
+            continue;
+        };
+
+        let text = CorpusText {
+            uri,
+            status,
+            path: entry.path(),
+        };
+
+        match texts[first..].iter_mut().find(|t| t.uri == text.uri) {
+            Some(existing) if existing.status > status => *existing = text,
+            Some(_) => {}
+            None => texts.push(text),
+        }
+    }
+
+    Ok(())
+}
+
+// The name of the folder being walked is passed along, so that we can tell whether its
+// subfolders are books
+fn walk(dir: &Path, dir_name: &str, texts: &mut Vec<CorpusText>) -> io::Result<()> {
+    let author = Some(dir_name).filter(|name| is_author_folder(name));
+
+    for entry in sorted_entries(dir)? {
+        let name = entry.file_name();
+        let Some(name) = name.to_str() else {
+            continue;
+        };
+
+        // Skip .git and the like
+        if name.starts_with('.') || !entry.file_type()?.is_dir() {
+            continue;
+        }
+
+        let book = author
+            .and_then(|author| name.strip_prefix(author))
+            .and_then(|book| book.strip_prefix('.'))
+            .filter(|book| !book.is_empty() && !book.contains('.'));
+
+        match (author, book) {
+            (Some(author), Some(book)) => book_texts(author, book, &entry.path(), texts)?,
+            _ => walk(&entry.path(), name, texts)?,
+        }
+    }
+
+    Ok(())
+}
+
+/// Find every text version under `root`, which can be an author folder, its parent (e.g., a
+/// `data` folder), or anything above that. Versions come out sorted by URI
+///
+/// # Errors
+///
+/// Will return an error if a folder can't be read.
+pub fn corpus_texts<P: AsRef<Path>>(root: P) -> io::Result<Vec<CorpusText>> {
+    let mut texts = Vec::new();
+    let root = root.as_ref();
+    let root_name = root.file_name().and_then(|name| name.to_str());
+
+    walk(root, root_name.unwrap_or_default(), &mut texts)?;
+
+    texts.sort_by_key(|text| text.uri.to_string());
+
+    Ok(texts)
+}
+
+/// Parse every text under `root`, one at a time, each along with where it came from
+///
+/// # Errors
+///
+/// Same as [`corpus_texts`]. Parse errors are per text.
+pub fn corpus_documents<P: AsRef<Path>>(
+    root: P,
+) -> io::Result<impl Iterator<Item = (CorpusText, Result<Document<'static>, ParseError>)>> {
+    let texts = corpus_texts(root)?;
+
+    Ok(texts.into_iter().map(|text| {
+        let doc = text.parse();
+        (text, doc)
+    }))
+}
+
+/// Like [`corpus_documents`], but parsing in parallel (see [`parse_files`])
+///
+/// # Errors
+///
+/// Same as [`corpus_texts`]. Parse errors are per text.
+pub fn parse_corpus<P: AsRef<Path>>(
+    root: P,
+) -> io::Result<Vec<(CorpusText, Result<Document<'static>, ParseError>)>> {
+    let texts = corpus_texts(root)?;
+
+    let paths: Vec<&Path> = texts.iter().map(|text| text.path.as_path()).collect();
+    let docs = parse_files(&paths);
+
+    Ok(texts.into_iter().zip(docs).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parser, UriPart};
+
+    // A small corpus in a temporary folder, removed when dropped
+    struct TempCorpus(PathBuf);
+
+    impl TempCorpus {
+        fn new(name: &str, files: &[&str]) -> Self {
+            let root = std::env::temp_dir().join(format!("oimdp-{name}-{}", std::process::id()));
+            let _ = fs::remove_dir_all(&root);
+
+            let text = fs::read_to_string("test2.md").unwrap();
+            for file in files {
+                let path = root.join(file);
+                fs::create_dir_all(path.parent().unwrap()).unwrap();
+                fs::write(path, &text).unwrap();
+            }
+
+            Self(root)
+        }
+    }
+
+    impl Drop for TempCorpus {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn layout() {
+        let corpus = TempCorpus::new(
+            "layout",
+            &[
+                "0025AH/data/0213IbnHisham/0213IbnHisham.SiraNabawiyya/0213IbnHisham.SiraNabawiyya.Shamela0023833-ara1",
+                "0025AH/data/0213IbnHisham/0213IbnHisham.SiraNabawiyya/0213IbnHisham.SiraNabawiyya.Shamela0023833-ara1.completed",
+                "0025AH/data/0213IbnHisham/0213IbnHisham.SiraNabawiyya/0213IbnHisham.SiraNabawiyya.Shamela0023833-ara1.yml",
+                "0025AH/data/0213IbnHisham/0213IbnHisham.SiraNabawiyya/0213IbnHisham.SiraNabawiyya.JK000001-ara1.inProgress",
+                "0025AH/data/0213IbnHisham/0213IbnHisham.SiraNabawiyya/0213IbnHisham.SiraNabawiyya.JK000001-ara1.mARkdown",
+                "0025AH/data/0213IbnHisham/0213IbnHisham.SiraNabawiyya/0213IbnHisham.SiraNabawiyya.yml",
+                "0025AH/data/0213IbnHisham/0213IbnHisham.yml",
+                "0025AH/data/0213IbnHisham/0213IbnHisham.SiraNabawiyya/README.md",
+                "0025AH/data/0150AbuHanifa/0150AbuHanifa.FiqhAkbar/0150AbuHanifa.FiqhAkbar.Shamela0001234-ara1",
+                "0025AH/data/0213IbnHisham/0213IbnHisham.SiraNabawiyya/0213IbnHisham.SiraNabawiyya.Shamela0023833",
+                "0025AH/.git/0100Fake/0100Fake.Book/0100Fake.Book.Version-ara1",
+            ],
+        );
+
+        let texts = corpus_texts(&corpus.0).unwrap();
+        let found: Vec<(String, TextStatus)> = texts
+            .iter()
+            .map(|text| (text.uri.to_string(), text.status))
+            .collect();
+
+        assert_eq!(
+            found,
+            [
+                (
+                    "0150AbuHanifa.FiqhAkbar.Shamela0001234-ara1".into(),
+                    TextStatus::Raw
+                ),
+                (
+                    "0213IbnHisham.SiraNabawiyya.JK000001-ara1".into(),
+                    TextStatus::Markdown
+                ),
+                (
+                    "0213IbnHisham.SiraNabawiyya.Shamela0023833-ara1".into(),
+                    TextStatus::Completed
+                ),
+            ]
+        );
+        assert_eq!(texts[1].uri.author, "IbnHisham");
+        assert_eq!(texts[1].uri.book.as_deref(), Some("SiraNabawiyya"));
+        assert!(texts[1].path.ends_with(
+            "0213IbnHisham.SiraNabawiyya/0213IbnHisham.SiraNabawiyya.JK000001-ara1.mARkdown"
+        ));
+
+        // Starting from an author folder
+        let author = corpus_texts(corpus.0.join("0025AH/data/0213IbnHisham")).unwrap();
+        assert_eq!(author, texts[1..]);
+
+        let full_text = fs::read_to_string("test2.md").unwrap();
+        let expected = parser(&full_text).unwrap();
+
+        let docs: Vec<_> = corpus_documents(&corpus.0).unwrap().collect();
+        assert_eq!(docs.len(), 3);
+        assert_eq!(docs[2].1.as_ref().unwrap(), &expected);
+
+        // Every file here has test2.md in it, which claims to be another book
+        let header = expected.metadata.uri().unwrap().unwrap();
+        assert_eq!(
+            header.mismatches(&docs[2].0.uri),
+            [UriPart::Date, UriPart::Author, UriPart::Book]
+        );
+
+        let parsed = parse_corpus(&corpus.0).unwrap();
+        assert_eq!(parsed, docs);
+    }
+
+    #[test]
+    fn missing_root() {
+        assert!(corpus_texts("no/such/folder").is_err());
+    }
+}
@@ -0,0 +1,226 @@
+use crate::{pages::PageIndex, sections::SectionTree, structures::*};
+#[cfg(feature = "serde")]
+use serde::Serialize;
+
+// Named entities, document-wide
+// In the content, an entity is a NamedEntity tag followed by one or more NamedEntityText
+// pieces (more than one when the words run on past a line break or a page marker). Here they
+// are put back together, one occurrence per tag, with everything needed to index them
+
+/// Words of context taken on either side of an entity, unless asked for otherwise
+pub const ENTITY_CONTEXT_WORDS: usize = 10;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct EntityOccurrence<'a> {
+    pub ne_type: EntityType,
+    // The entity's words, with the pieces joined by spaces, less the prefix
+    pub text: String,
+    pub prefix: &'a str,
+    // Index of the line with the tag in the document's content
+    pub index: usize,
+    // From the start of the tag to the end of the entity's last word, which may be on a
+    // later line. The line number is the tag's
+    pub span: Span,
+    // The page the tag is on, i.e., the next page marker (see PageIndex). None after the
+    // last marker
+    pub page: Option<&'a PageNumber<'a>>,
+    // See Section::path; empty before the first header
+    pub section_path: Vec<usize>,
+    // Index of the biography (or event) the entity is in, if any. One runs from its marker
+    // to the next marker of its kind or the next header, so it can have several paragraphs
+    pub biography: Option<usize>,
+    // Plain text on either side of the entity, a given number of words each way. The
+    // prefix counts as part of the entity
+    pub before: String,
+    pub after: String,
+}
+
+// Where an occurrence's words are in the document's flat list of words
+struct Words {
+    start: usize,
+    end: usize,
+}
+
+fn join(words: &[String]) -> String {
+    words.join(" ")
+}
+
+impl<'a> Document<'a> {
+    /// Every named entity in the document, in order, with `context` words of plain text on
+    /// either side (see [`ENTITY_CONTEXT_WORDS`])
+    #[must_use]
+    pub fn entities(&'a self, context: usize) -> Vec<EntityOccurrence<'a>> {
+        let tree = SectionTree::new(self);
+        let pages = PageIndex::new(self);
+
+        let mut found: Vec<EntityOccurrence> = Vec::new();
+        let mut positions: Vec<Words> = Vec::new();
+        // All the plain text, word by word, for the context
+        let mut words: Vec<String> = Vec::new();
+
+        // The occurrence still taking words, and how many it's still owed
+        let mut open: Option<u32> = None;
+        let mut biography = None;
+
+        for (i, item) in self.content.iter().enumerate() {
+            match item {
+                Content::Line(line) => {
+                    for part in &line.parts {
+                        match part {
+                            LinePart::NamedEntity {
+                                extent,
+                                ne_type,
+                                span,
+                                ..
+                            } => {
+                                open = Some(*extent);
+                                found.push(EntityOccurrence {
+                                    ne_type: *ne_type,
+                                    text: String::new(),
+                                    prefix: "",
+                                    index: i,
+                                    span: *span,
+                                    page: pages.page_at(span.start).map(|page| page.number),
+                                    section_path: tree
+                                        .find(i)
+                                        .last()
+                                        .map_or_else(Vec::new, |section| section.path.clone()),
+                                    biography,
+                                    before: String::new(),
+                                    after: String::new(),
+                                });
+                                positions.push(Words {
+                                    start: words.len(),
+                                    end: words.len(),
+                                });
+                            }
+                            LinePart::NamedEntityText {
+                                prefix, text, span, ..
+                            } => {
+                                let piece = format!("{prefix}{text}");
+                                words.extend(piece.split_whitespace().map(Into::into));
+
+                                let (Some(left), Some(entity), Some(position)) =
+                                    (open, found.last_mut(), positions.last_mut())
+                                else {
+                                    continue;
+                                };
+
+                                if entity.text.is_empty() {
+                                    entity.prefix = prefix;
+                                } else {
+                                    entity.text.push(' ');
+                                }
+                                entity.text.push_str(text);
+                                entity.span.end = span.end;
+                                position.end = words.len();
+
+                                let taken = u32::try_from(text.split(' ').count()).unwrap_or(left);
+                                open = Some(left.saturating_sub(taken)).filter(|&left| left > 0);
+                            }
+                            LinePart::TextPart { text, .. } => {
+                                open = None;
+                                words.extend(text.split_whitespace().map(Into::into));
+                            }
+                            _ => {}
+                        }
+                    }
+                }
+                Content::PageNumber(_) => {}
+                Content::BioOrEvent { .. } => {
+                    open = None;
+                    biography = Some(i);
+                }
+                Content::SectionHeader { value, .. } => {
+                    open = None;
+                    biography = None;
+                    words.extend(value.split_whitespace().map(Into::into));
+                }
+                _ => open = None,
+            }
+        }
+
+        for (entity, position) in found.iter_mut().zip(&positions) {
+            let before = position.start.saturating_sub(context);
+            let after = (position.end + context).min(words.len());
+
+            entity.before = join(&words[before..position.start]);
+            entity.after = join(&words[position.end..after]);
+        }
+
+        found
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser;
+
+    #[test]
+    fn entities() {
+        let input = "######OpenITI#\n#META#Header#End#\n### | باب\n### $ @PER03 أحمد بن\n~~حنبل ولد @TOP11 ببغداد PageV01P001 وقال\n# عن @PER03 محمد بن PageV01P002 إسحاق قال\n### | باب آخر\n# @SOC01 الحنفية";
+        let doc = parser(input).unwrap();
+        let entities = doc.entities(2);
+
+        let found: Vec<(EntityType, &str, &str)> = entities
+            .iter()
+            .map(|entity| (entity.ne_type, entity.prefix, entity.text.as_str()))
+            .collect();
+        assert_eq!(
+            found,
+            [
+                (EntityType::Per, "", "أحمد بن حنبل"),
+                (EntityType::Top, "ب", "بغداد"),
+                (EntityType::Per, "", "محمد بن إسحاق"),
+                (EntityType::Soc, "", "الحنفية"),
+            ]
+        );
+
+        // Pages, sections, and biographies
+        let pages: Vec<Option<&str>> = entities
+            .iter()
+            .map(|entity| entity.page.map(|page| page.page.as_ref()))
+            .collect();
+        assert_eq!(pages, [Some("001"), Some("001"), Some("002"), None]);
+
+        assert_eq!(entities[0].section_path, [1]);
+        assert_eq!(entities[3].section_path, [2]);
+
+        let bio = entities[0].biography.unwrap();
+        assert!(doc.content[bio].is_bio_or_event());
+        assert_eq!(entities[2].biography, Some(bio));
+        assert_eq!(entities[3].biography, None);
+
+        // Context
+        assert_eq!(entities[1].before, "حنبل ولد");
+        assert_eq!(entities[1].after, "وقال عن");
+        assert_eq!(entities[2].after, "قال باب");
+        assert_eq!(entities[0].before, "باب");
+
+        // From the tag to the end of the last word, on the next line
+        assert_eq!(&input[entities[0].span.range()], "@PER03 أحمد بن\n~~حنبل");
+    }
+
+    #[test]
+    fn whole_file() {
+        let full_text = std::fs::read_to_string("test.md").unwrap();
+        let doc = parser(&full_text).unwrap();
+        let entities = doc.entities(ENTITY_CONTEXT_WORDS);
+
+        let tags = doc
+            .content
+            .iter()
+            .filter_map(Content::as_line)
+            .flat_map(|line| &line.parts)
+            .filter(|part| part.is_named_entity())
+            .count();
+        assert_eq!(entities.len(), tags);
+
+        for entity in &entities {
+            assert!(entity.before.split(' ').count() <= ENTITY_CONTEXT_WORDS);
+            assert!(entity.after.split(' ').count() <= ENTITY_CONTEXT_WORDS);
+        }
+    }
+}
@@ -0,0 +1,193 @@
+use std::{error, fmt, io};
+
+// Everything that can make parsing fail. Line numbers and columns are 1-based;
+// columns are counted in characters, not bytes, since the text is mostly Arabic
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ParseError {
+    BadMagic {
+        line: usize,
+        column: usize,
+    },
+    // The rest are only raised in strict mode (see ParseOptions); otherwise the parser keeps
+    // going, and notes them in the diagnostics
+    MalformedPageTag {
+        line: usize,
+        column: usize,
+        tag: String,
+    },
+    MalformedEntityTag {
+        line: usize,
+        column: usize,
+        tag: String,
+    },
+    BadMetadataLine {
+        line: usize,
+        column: usize,
+        text: String,
+    },
+    // Only when reading from a stream or a file. io::Error can't be cloned or compared, so
+    // we keep its kind and message. Line 0 means the file couldn't be opened
+    Io {
+        line: usize,
+        kind: io::ErrorKind,
+        message: String,
+    },
+}
+
+impl ParseError {
+    #[must_use]
+    pub const fn line(&self) -> usize {
+        match self {
+            Self::BadMagic { line, .. }
+            | Self::MalformedPageTag { line, .. }
+            | Self::MalformedEntityTag { line, .. }
+            | Self::BadMetadataLine { line, .. }
+            | Self::Io { line, .. } => *line,
+        }
+    }
+
+    #[must_use]
+    pub const fn column(&self) -> usize {
+        match self {
+            Self::BadMagic { column, .. }
+            | Self::MalformedPageTag { column, .. }
+            | Self::MalformedEntityTag { column, .. }
+            | Self::BadMetadataLine { column, .. } => *column,
+            Self::Io { .. } => 1,
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::BadMagic { line, column } => write!(
+                f,
+                "{line}:{column}: this does not appear to be an OpenITI mARkdown document"
+            ),
+            Self::MalformedPageTag { line, column, tag } => {
+                write!(f, "{line}:{column}: malformed page tag `{tag}`")
+            }
+            Self::MalformedEntityTag { line, column, tag } => {
+                write!(f, "{line}:{column}: malformed named-entity tag `{tag}`")
+            }
+            Self::BadMetadataLine { line, column, text } => {
+                write!(
+                    f,
+                    "{line}:{column}: metadata line without `::` separator `{text}`"
+                )
+            }
+            Self::Io {
+                line: 0, message, ..
+            } => write!(f, "could not open file: {message}"),
+            Self::Io { line, message, .. } => write!(f, "{line}: could not read line: {message}"),
+        }
+    }
+}
+
+impl error::Error for ParseError {}
+
+// What can be wrong with an OpenITI URI. Each variant has the part that didn't pass
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum UriError {
+    BadDate { text: String },
+    BadAuthor { text: String },
+    BadBook { text: String },
+    BadVersion { text: String },
+    TooManyParts { text: String },
+}
+
+impl fmt::Display for UriError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::BadDate { text } => {
+                write!(f, "URI `{text}` does not start with a four-digit date")
+            }
+            Self::BadAuthor { text } => write!(f, "invalid author name `{text}` in URI"),
+            Self::BadBook { text } => write!(f, "invalid book title `{text}` in URI"),
+            Self::BadVersion { text } => write!(f, "invalid version `{text}` in URI"),
+            Self::TooManyParts { text } => write!(f, "URI `{text}` has too many parts"),
+        }
+    }
+}
+
+impl error::Error for UriError {}
+
+// What can go wrong reading a YAML sidecar
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SidecarError {
+    BadLine {
+        line: usize,
+        text: String,
+    },
+    BadUri(UriError),
+    Io {
+        kind: io::ErrorKind,
+        message: String,
+    },
+}
+
+impl fmt::Display for SidecarError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::BadLine { line, text } => {
+                write!(f, "{line}: sidecar line without `:` separator `{text}`")
+            }
+            Self::BadUri(err) => write!(f, "bad URI in sidecar: {err}"),
+            Self::Io { message, .. } => write!(f, "could not read sidecar: {message}"),
+        }
+    }
+}
+
+impl error::Error for SidecarError {}
+
+// What can go wrong importing entity predictions. Problems with single entities are
+// reported as conflicts instead; these are for predictions that can't be used at all
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ImportError {
+    Parse(ParseError),
+    // A line of the predictions that isn't what it should be
+    BadLine {
+        line: usize,
+        text: String,
+    },
+    UnknownLabel {
+        line: usize,
+        label: String,
+    },
+    // CoNLL predictions are matched to the text token by token
+    TokenMismatch {
+        line: usize,
+        expected: String,
+        found: String,
+    },
+    TokenCount {
+        expected: usize,
+        found: usize,
+    },
+}
+
+impl fmt::Display for ImportError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Parse(err) => write!(f, "could not parse text: {err}"),
+            Self::BadLine { line, text } => write!(f, "{line}: bad prediction `{text}`"),
+            Self::UnknownLabel { line, label } => write!(f, "{line}: unknown label `{label}`"),
+            Self::TokenMismatch {
+                line,
+                expected,
+                found,
+            } => write!(
+                f,
+                "{line}: prediction for `{found}` where the text has `{expected}`"
+            ),
+            Self::TokenCount { expected, found } => write!(
+                f,
+                "predictions for {found} tokens, but the text has {expected}"
+            ),
+        }
+    }
+}
+
+impl error::Error for ImportError {}
@@ -0,0 +1,706 @@
+use crate::{
+    conll::{conll_tokens, EntityLabels},
+    error::ImportError,
+    is_diacritic, offset_in, parser,
+    structures::*,
+    tags::LINE,
+    writer::entity_tag,
+};
+#[cfg(feature = "serde")]
+use serde::Deserialize;
+use std::ops::Range;
+
+// Importing predictions
+// The way back from the CoNLL export: a model's entity predictions become tags in the
+// source text. Only tags are added; everything else in the file is left as it was. Entities
+// that can't be tagged (because they overlap a tag that's already there, say) are reported
+// as conflicts and left out
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConflictKind {
+    // With a tag already in the text, or another prediction
+    Overlaps,
+    // The prediction doesn't match up with the text
+    Unaligned,
+    // Tags only have one digit each for the prefix and the number of words
+    TooLong,
+    // Entities can run on into a `~~` line, but not into a new paragraph, etc.
+    CrossesBreak,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ImportConflict {
+    pub kind: ConflictKind,
+    // Source line of the entity's first token (or of the prediction, if it couldn't be found)
+    pub line: usize,
+    // The entity's tokens, joined by spaces
+    pub text: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TagImport {
+    // The source text, with the new tags
+    pub output: String,
+    pub inserted: usize,
+    pub conflicts: Vec<ImportConflict>,
+}
+
+// A token of the text, in the same order as in the CoNLL export
+struct SourceToken<'a> {
+    text: &'a str,
+    // Byte offset in the source, unless the line part's text isn't there as it is
+    start: Option<usize>,
+    // Where the word (in the parser's sense: split on spaces) the token is part of starts
+    word_start: Option<usize>,
+    word: usize,
+    // Content index of the line
+    item: usize,
+    line: usize,
+    // The tagged entity the token is part of, if any, numbered by where it starts
+    entity: Option<(usize, EntityType)>,
+}
+
+// A predicted entity, as a range of tokens
+struct Prediction {
+    ne_type: EntityType,
+    tokens: Range<usize>,
+}
+
+struct Tokenizer<'a> {
+    tokens: Vec<SourceToken<'a>>,
+    word: usize,
+    word_start: Option<usize>,
+}
+
+impl<'a> Tokenizer<'a> {
+    // A prefix and the first word of its entity's text are one word
+    fn push(
+        &mut self,
+        input: &str,
+        text: &'a str,
+        start: usize,
+        entity: Option<(usize, EntityType)>,
+        joined: bool,
+    ) {
+        let start = (input.get(start..start + text.len()) == Some(text)).then_some(start);
+
+        for (n, word) in text.split(' ').enumerate() {
+            let at = start.and_then(|start| Some(start + offset_in(text, word)?));
+
+            if n > 0 || !joined {
+                self.word += 1;
+                self.word_start = at;
+            }
+
+            for token in conll_tokens(word) {
+                self.tokens.push(SourceToken {
+                    text: token,
+                    start: at.and_then(|at| Some(at + offset_in(word, token)?)),
+                    word_start: self.word_start,
+                    word: self.word,
+                    item: 0,
+                    line: 0,
+                    entity,
+                });
+            }
+        }
+    }
+}
+
+fn source_tokens<'a>(input: &str, doc: &'a Document<'a>) -> Vec<SourceToken<'a>> {
+    let mut tokens = Tokenizer {
+        tokens: Vec::new(),
+        word: 0,
+        word_start: None,
+    };
+    // The entity whose pieces are being read
+    let mut entity = None;
+
+    for (i, item) in doc.content.iter().enumerate() {
+        let Content::Line(line) = item else {
+            continue;
+        };
+        let first = tokens.tokens.len();
+
+        for part in &line.parts {
+            match part {
+                LinePart::TextPart { text, span } => {
+                    tokens.push(input, text, span.start, None, false);
+                }
+                LinePart::NamedEntity { ne_type, .. } => {
+                    entity = Some((tokens.tokens.len(), *ne_type));
+                }
+                LinePart::NamedEntityText {
+                    prefix, text, span, ..
+                } => {
+                    let start = span.start.saturating_sub(prefix.len());
+                    tokens.push(input, prefix, start, None, false);
+                    tokens.push(input, text, span.start, entity, !prefix.is_empty());
+                }
+                _ => {}
+            }
+        }
+
+        for token in &mut tokens.tokens[first..] {
+            token.item = i;
+            token.line = line.span.line;
+        }
+    }
+
+    tokens.tokens
+}
+
+fn join(tokens: &[SourceToken]) -> String {
+    let texts: Vec<&str> = tokens.iter().map(|token| token.text).collect();
+    texts.join(" ")
+}
+
+// Whether the prediction is a tag that's already there: the same type, and the same tokens
+fn agrees(tokens: &[SourceToken], prediction: &Prediction) -> bool {
+    let Some(Some((number, ne_type))) = tokens.get(prediction.tokens.start).map(|t| t.entity)
+    else {
+        return false;
+    };
+    let outside = |token: Option<&SourceToken>| {
+        token.is_none_or(|token| token.entity.map(|(n, _)| n) != Some(number))
+    };
+
+    ne_type == prediction.ne_type
+        && tokens[prediction.tokens.clone()]
+            .iter()
+            .all(|token| token.entity == Some((number, ne_type)))
+        && outside(
+            prediction
+                .tokens
+                .start
+                .checked_sub(1)
+                .and_then(|i| tokens.get(i)),
+        )
+        && outside(tokens.get(prediction.tokens.end))
+}
+
+// Where the tag for an entity goes, and the tag itself
+fn tag_for(
+    input: &str,
+    doc: &Document,
+    tokens: &[SourceToken],
+    prediction: &Prediction,
+) -> Result<(usize, String), ConflictKind> {
+    let entity = &tokens[prediction.tokens.clone()];
+    let (Some(first), Some(last)) = (entity.first(), entity.last()) else {
+        return Err(ConflictKind::Unaligned);
+    };
+
+    for pair in entity.windows(2) {
+        let continues = match &doc.content[pair[1].item] {
+            Content::Line(line) => input[line.span.range()].starts_with(LINE),
+            _ => false,
+        };
+
+        if pair[0].item != pair[1].item && !continues {
+            return Err(ConflictKind::CrossesBreak);
+        }
+    }
+
+    // Any word that the entity has a part of has to be free
+    let before = tokens[..prediction.tokens.start]
+        .iter()
+        .rev()
+        .take_while(|token| token.word == first.word);
+    let after = tokens[prediction.tokens.end..]
+        .iter()
+        .take_while(|token| token.word == last.word);
+
+    if entity
+        .iter()
+        .chain(before)
+        .chain(after)
+        .any(|token| token.entity.is_some())
+    {
+        return Err(ConflictKind::Overlaps);
+    }
+
+    let (Some(start), Some(word_start)) = (first.start, first.word_start) else {
+        return Err(ConflictKind::Unaligned);
+    };
+    if entity.iter().any(|token| token.start.is_none()) {
+        return Err(ConflictKind::Unaligned);
+    }
+
+    // The tag goes after any punctuation before the entity, e.g., an opening bracket, so
+    // that the prefix is only letters (and their vowels, which the parser counts along with
+    // the letter they're on)
+    let at = input[word_start..start]
+        .char_indices()
+        .rfind(|&(_, c)| !(c.is_alphabetic() || is_diacritic(c)))
+        .map_or(word_start, |(i, c)| word_start + i + c.len_utf8());
+    let prefix = input[at..start]
+        .chars()
+        .filter(|&c| !is_diacritic(c))
+        .count();
+    let extent = last.word - first.word + 1;
+
+    if prefix > 9 || extent > 9 {
+        return Err(ConflictKind::TooLong);
+    }
+
+    Ok((
+        at,
+        format!("{}{prefix}{extent} ", entity_tag(prediction.ne_type)),
+    ))
+}
+
+fn insert_tags(input: &str, predictions: &[Prediction], doc: &Document) -> TagImport {
+    let mut tokens = source_tokens(input, doc);
+    let mut tags = Vec::new();
+    let mut conflicts = Vec::new();
+
+    for prediction in predictions {
+        if agrees(&tokens, prediction) {
+            continue;
+        }
+
+        match tag_for(input, doc, &tokens, prediction) {
+            Ok(tag) => {
+                // Later predictions can't have these words, either
+                for token in &mut tokens[prediction.tokens.clone()] {
+                    token.entity = Some((prediction.tokens.start, prediction.ne_type));
+                }
+
+                tags.push(tag);
+            }
+            Err(kind) => conflicts.push(ImportConflict {
+                kind,
+                line: tokens
+                    .get(prediction.tokens.start)
+                    .map_or(0, |token| token.line),
+                text: join(&tokens[prediction.tokens.clone()]),
+            }),
+        }
+    }
+
+    tags.sort_by_key(|&(at, _)| at);
+
+    let mut output = String::with_capacity(input.len() + tags.len() * 8);
+    let mut copied = 0;
+
+    for (at, tag) in &tags {
+        output.push_str(&input[copied..*at]);
+        output.push_str(tag);
+        copied = *at;
+    }
+
+    output.push_str(&input[copied..]);
+
+    TagImport {
+        output,
+        inserted: tags.len(),
+        conflicts,
+    }
+}
+
+fn label_type(labels: &EntityLabels, name: &str) -> Option<EntityType> {
+    [
+        EntityType::Per,
+        EntityType::Top,
+        EntityType::Soc,
+        EntityType::Src,
+    ]
+    .into_iter()
+    .find(|&ne_type| labels.get(ne_type) == name)
+}
+
+/// Add tags to `input` for the entities in CoNLL-style `predictions`
+///
+/// That's a token and its label on each line, with whitespace in between, in the same order
+/// as [`Document::write_conll`] puts them (blank lines don't matter). Labels can be BIO or
+/// BIOES, with the names in `labels`. Tags that are already there are kept, and predictions
+/// that would overlap them are reported as conflicts
+///
+/// # Errors
+///
+/// Will return an error if `input` can't be parsed, or if the predictions don't go with it.
+pub fn import_conll(
+    input: &str,
+    predictions: &str,
+    labels: &EntityLabels,
+) -> Result<TagImport, ImportError> {
+    let doc = parser(input).map_err(ImportError::Parse)?;
+    let tokens = source_tokens(input, &doc);
+
+    let mut found = Vec::new();
+    let mut open: Option<Prediction> = None;
+    let mut count = 0;
+
+    for (i, text) in predictions.lines().enumerate() {
+        let line = i + 1;
+        let mut fields = text.split_whitespace();
+
+        let (token, label) = match (fields.next(), fields.next_back()) {
+            (None, _) => continue,
+            (Some(token), Some(label)) => (token, label),
+            (Some(_), None) => {
+                return Err(ImportError::BadLine {
+                    line,
+                    text: text.into(),
+                })
+            }
+        };
+
+        let expected = tokens.get(count).map_or("", |token| token.text);
+        if token != expected {
+            return Err(ImportError::TokenMismatch {
+                line,
+                expected: expected.into(),
+                found: token.into(),
+            });
+        }
+
+        let entity = match label.split_once('-') {
+            None if label == "O" => None,
+            Some((position @ ("B" | "I" | "E" | "S"), name)) => {
+                let ne_type =
+                    label_type(labels, name).ok_or_else(|| ImportError::UnknownLabel {
+                        line,
+                        label: label.into(),
+                    })?;
+                Some((position, ne_type))
+            }
+            _ => {
+                return Err(ImportError::UnknownLabel {
+                    line,
+                    label: label.into(),
+                })
+            }
+        };
+
+        // I and E carry on an entity of the same type; anything else ends it
+        match (entity, &mut open) {
+            (Some(("I" | "E", ne_type)), Some(current)) if current.ne_type == ne_type => {
+                current.tokens.end = count + 1;
+            }
+            (Some((_, ne_type)), _) => {
+                found.extend(open.take());
+                open = Some(Prediction {
+                    ne_type,
+                    tokens: count..count + 1,
+                });
+            }
+            (None, _) => found.extend(open.take()),
+        }
+
+        if matches!(entity, Some(("E" | "S", _))) {
+            found.extend(open.take());
+        }
+
+        count += 1;
+    }
+
+    found.extend(open);
+
+    if count != tokens.len() {
+        return Err(ImportError::TokenCount {
+            expected: tokens.len(),
+            found: count,
+        });
+    }
+
+    Ok(insert_tags(input, &found, &doc))
+}
+
+// One line of JSONL predictions
+#[cfg(feature = "serde")]
+#[derive(Deserialize)]
+struct SpanPrediction {
+    // Source line number
+    line: usize,
+    // In characters, not bytes, from the start of the line's text_only
+    start: usize,
+    end: usize,
+    label: String,
+}
+
+// Which of the line's tokens each token of its text_only is made of. The text_only version
+// of an entity with a prefix is one token, where the line's parts have two
+#[cfg(feature = "serde")]
+fn align_text_only(
+    text_only: &str,
+    line_tokens: &[SourceToken],
+) -> Option<Vec<(Range<usize>, Range<usize>)>> {
+    let mut aligned = Vec::new();
+    let mut next = 0;
+
+    for token in conll_tokens(text_only) {
+        let start = offset_in(text_only, token)?;
+        let first = next;
+        let mut joined = String::new();
+
+        while joined.len() < token.len() {
+            joined.push_str(line_tokens.get(next)?.text);
+            next += 1;
+        }
+
+        if joined != token {
+            return None;
+        }
+
+        aligned.push((start..start + token.len(), first..next));
+    }
+
+    (next == line_tokens.len()).then_some(aligned)
+}
+
+/// Like [`import_conll`], but with predictions in JSON Lines, one entity per line
+///
+/// E.g., `{"line": 12, "start": 4, "end": 16, "label": "PER"}`: the source line the entity
+/// is on, where it starts and ends in the line's `text_only` (in characters, as Python
+/// counts them), and its label (from `labels`).
+///
+/// # Errors
+///
+/// Will return an error if `input` can't be parsed, or if a line of the predictions isn't
+/// valid.
+#[cfg(feature = "serde")]
+pub fn import_spans(
+    input: &str,
+    predictions: &str,
+    labels: &EntityLabels,
+) -> Result<TagImport, ImportError> {
+    let doc = parser(input).map_err(ImportError::Parse)?;
+    let tokens = source_tokens(input, &doc);
+
+    let mut found = Vec::new();
+    let mut unaligned = Vec::new();
+
+    for (i, text) in predictions.lines().enumerate() {
+        if text.trim().is_empty() {
+            continue;
+        }
+
+        let bad = || ImportError::BadLine {
+            line: i + 1,
+            text: text.into(),
+        };
+
+        let span: SpanPrediction = serde_json::from_str(text).map_err(|_| bad())?;
+        let ne_type = label_type(labels, &span.label).ok_or_else(|| ImportError::UnknownLabel {
+            line: i + 1,
+            label: span.label.clone(),
+        })?;
+
+        let mut conflict = || {
+            unaligned.push(ImportConflict {
+                kind: ConflictKind::Unaligned,
+                line: span.line,
+                text: text.into(),
+            });
+        };
+
+        // The line, its tokens, and its text_only
+        let Some((item, line)) = doc.content.iter().enumerate().find_map(|(i, item)| {
+            item.as_line()
+                .filter(|line| line.span.line == span.line)
+                .map(|line| (i, line))
+        }) else {
+            conflict();
+            continue;
+        };
+
+        let first = tokens.partition_point(|token| token.item < item);
+        let last = tokens.partition_point(|token| token.item <= item);
+        let text_only = line.text_only.as_deref().unwrap_or_default();
+
+        let byte = |chars: usize| {
+            text_only
+                .char_indices()
+                .map(|(at, _)| at)
+                .chain(std::iter::once(text_only.len()))
+                .nth(chars)
+        };
+
+        let (Some(start), Some(end), Some(aligned)) = (
+            byte(span.start),
+            byte(span.end),
+            align_text_only(text_only, &tokens[first..last]),
+        ) else {
+            conflict();
+            continue;
+        };
+
+        // Every token the span touches
+        let touched: Vec<&Range<usize>> = aligned
+            .iter()
+            .filter(|(bytes, _)| bytes.start < end && start < bytes.end)
+            .map(|(_, tokens)| tokens)
+            .collect();
+
+        match (touched.first(), touched.last()) {
+            (Some(from), Some(to)) => found.push(Prediction {
+                ne_type,
+                tokens: first + from.start..first + to.end,
+            }),
+            _ => conflict(),
+        }
+    }
+
+    found.sort_by_key(|prediction| prediction.tokens.start);
+
+    let mut import = insert_tags(input, &found, &doc);
+    import.conflicts.extend(unaligned);
+
+    Ok(import)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ConllOptions, TagScheme};
+    use std::fmt::Write;
+
+    const INPUT: &str = "######OpenITI#\n#META#Header#End#\n# حدثنا محمد بن\n~~إسحاق عن زيد، وبغداد. ثم\n# @PER01 عمرو قال (الحنفية) عن علي\n";
+
+    fn conll(input: &str, labels: &[&str]) -> String {
+        let doc = parser(input).unwrap();
+        let mut out = String::new();
+
+        let tokens = doc.conll_segments(&ConllOptions::default()).concat();
+        for (token, label) in tokens.iter().zip(labels) {
+            writeln!(out, "{}\t{label}", token.text).unwrap();
+        }
+
+        out
+    }
+
+    #[test]
+    fn from_conll() {
+        let labels = [
+            "O", "B-PER", "I-PER", "I-PER", "O", "B-PER", "O", "B-TOP", "O", "O", //
+            "B-PER", "B-PER", "O", "B-SOC", "O", "O", "B-PER",
+        ];
+        let predictions = conll(INPUT, &labels);
+        let import = import_conll(INPUT, &predictions, &EntityLabels::default()).unwrap();
+
+        assert_eq!(
+            import.output,
+            "######OpenITI#\n#META#Header#End#\n# حدثنا @PER03 محمد بن\n~~إسحاق عن @PER01 زيد، @TOP01 وبغداد. ثم\n# @PER01 عمرو @PER01 قال (@SOC01 الحنفية) عن @PER01 علي\n"
+        );
+        assert_eq!(import.inserted, 6);
+
+        // The prediction for عمرو is the tag that's already there
+        assert!(import.conflicts.is_empty());
+
+        // What we put in, we get back out
+        let doc = parser(&import.output).unwrap();
+        let entities: Vec<(&str, String)> = doc
+            .entities(0)
+            .into_iter()
+            .map(|entity| (entity.prefix, entity.text))
+            .collect();
+        assert_eq!(
+            entities,
+            [
+                ("", "محمد بن إسحاق".into()),
+                ("", "زيد،".into()),
+                ("", "وبغداد.".into()),
+                ("", "عمرو".into()),
+                ("", "قال".into()),
+                ("", "الحنفية)".into()),
+                ("", "علي".into())
+            ]
+        );
+
+        // Not if it gives the tag another type, though
+        let mut labels = ["O"; 17];
+        labels[10] = "B-SOC";
+        let import = import_conll(INPUT, &conll(INPUT, &labels), &EntityLabels::default());
+        assert_eq!(import.unwrap().conflicts[0].kind, ConflictKind::Overlaps);
+    }
+
+    #[test]
+    fn bioes_and_errors() {
+        let mut labels = ["O"; 17];
+        labels[1] = "B-PER";
+        labels[2] = "E-PER";
+        labels[3] = "S-PER";
+        labels[5] = "S-LOC";
+
+        let predictions = conll(INPUT, &labels);
+        let locations = EntityLabels {
+            top: "LOC".into(),
+            ..EntityLabels::default()
+        };
+        let import = import_conll(INPUT, &predictions, &locations).unwrap();
+
+        // Two entities side by side, and one across a line break
+        assert!(import
+            .output
+            .contains("# حدثنا @PER02 محمد بن\n~~@PER01 إسحاق عن @TOP01 زيد،"));
+        assert!(import.conflicts.is_empty());
+
+        // Across a paragraph break
+        let mut labels = ["O"; 17];
+        labels[9] = "B-PER";
+        labels[10] = "I-PER";
+        let import = import_conll(INPUT, &conll(INPUT, &labels), &locations).unwrap();
+        assert_eq!(import.inserted, 0);
+        assert_eq!(import.conflicts[0].kind, ConflictKind::CrossesBreak);
+
+        assert!(matches!(
+            import_conll(INPUT, &predictions.replace("زيد", "عمر"), &locations),
+            Err(ImportError::TokenMismatch { line: 6, .. })
+        ));
+        assert!(matches!(
+            import_conll(INPUT, &predictions, &EntityLabels::default()),
+            Err(ImportError::UnknownLabel { line: 6, .. })
+        ));
+        assert!(matches!(
+            import_conll(INPUT, "حدثنا\tO\n", &locations),
+            Err(ImportError::TokenCount { found: 1, .. })
+        ));
+
+        // The export goes straight back in
+        let doc = parser(INPUT).unwrap();
+        let mut out = Vec::new();
+        let options = ConllOptions {
+            scheme: TagScheme::Bioes,
+            ..ConllOptions::default()
+        };
+        doc.write_conll(&options, &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+
+        let import = import_conll(INPUT, &out, &EntityLabels::default()).unwrap();
+        assert_eq!(import.output, INPUT);
+        assert!(import.conflicts.is_empty());
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn from_spans() {
+        // Line 4 is `~~إسحاق عن زيد، وبغداد. ثم`, and line 5 has a tag already
+        let predictions = r#"
+{"line": 4, "start": 9, "end": 12, "label": "PER"}
+{"line": 4, "start": 15, "end": 21, "label": "TOP"}
+{"line": 5, "start": 0, "end": 4, "label": "PER"}
+{"line": 5, "start": 10, "end": 17, "label": "SOC"}
+{"line": 9, "start": 0, "end": 4, "label": "PER"}
+"#;
+        let import = import_spans(INPUT, predictions, &EntityLabels::default()).unwrap();
+
+        assert_eq!(
+            import.output,
+            "######OpenITI#\n#META#Header#End#\n# حدثنا محمد بن\n~~إسحاق عن @PER01 زيد، @TOP01 وبغداد. ثم\n# @PER01 عمرو قال (@SOC01 الحنفية) عن علي\n"
+        );
+
+        let kinds: Vec<(ConflictKind, usize)> = import
+            .conflicts
+            .iter()
+            .map(|conflict| (conflict.kind, conflict.line))
+            .collect();
+        assert_eq!(kinds, [(ConflictKind::Unaligned, 9)]);
+
+        assert!(matches!(
+            import_spans(INPUT, "{\"line\": 4}", &EntityLabels::default()),
+            Err(ImportError::BadLine { line: 1, .. })
+        ));
+    }
+}
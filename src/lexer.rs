@@ -0,0 +1,500 @@
+use crate::structures::{DateType, EntityType};
+use crate::tags::*;
+use std::borrow::Cow;
+
+// Inline tags
+// A hand-written lexer, replacing the big regex that parse_line used to split lines on (and
+// the other regexes that picked each piece apart again). It walks the line once, handing back
+// the text between tags along with the tags themselves, already broken into fields. Where more
+// than one tag could start at the same place, they're tried in the same order as the
+// alternatives of the old regex.
+// A couple of things are deliberately different: digits are ASCII only; and stripping tags
+// takes out exactly the tags found here, whereas the old chain of replacements could glue
+// leftover pieces together into new tags
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Tag<'a> {
+    Page {
+        vol: &'a str,
+        page: &'a str,
+    },
+    Auto {
+        resp: &'a str,
+        t_type: &'a str,
+        category: &'a str,
+        review: &'a str,
+    },
+    User {
+        user: &'a str,
+        t_type: &'a str,
+        t_subtype: &'a str,
+        t_subsubtype: &'a str,
+    },
+    Hemistich,
+    // With the number, for the ms0001 kind
+    Milestone(Option<&'a str>),
+    Matn,
+    Hukm,
+    RouteFrom,
+    RouteTowa,
+    RouteDist,
+    Date(DateType, &'a str),
+    Age(&'a str),
+    // The digits are kept as is: there should be two of them, but that's checked later
+    Entity(EntityType, &'a str),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Token<'a> {
+    // Slice of the line, for spans
+    pub src: &'a str,
+    // None for the text between tags
+    pub tag: Option<Tag<'a>>,
+}
+
+pub struct Lexer<'a> {
+    line: &'a str,
+    pos: usize,
+    // A tag found while looking past some text, to be handed out after it
+    next_tag: Option<Token<'a>>,
+}
+
+impl<'a> Lexer<'a> {
+    pub const fn new(line: &'a str) -> Self {
+        Self {
+            line,
+            pos: 0,
+            next_tag: None,
+        }
+    }
+}
+
+impl<'a> Iterator for Lexer<'a> {
+    type Item = Token<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(token) = self.next_tag.take() {
+            return Some(token);
+        }
+
+        if self.pos >= self.line.len() {
+            return None;
+        }
+
+        let start = self.pos;
+
+        // Only these characters can start a tag. They're all ASCII, so we can look for them
+        // byte by byte without landing in the middle of a character
+        let mut search = start;
+        while let Some(found) = self.line.as_bytes()[search..]
+            .iter()
+            .position(|b| matches!(b, b'@' | b'P' | b'%' | b'M' | b'm' | b'#'))
+        {
+            let at = search + found;
+
+            // A numbered milestone has to start a word, or we'd find them in Latin text
+            if self.line.as_bytes()[at] == b'm'
+                && at > 0
+                && self.line.as_bytes()[at - 1].is_ascii_alphanumeric()
+            {
+                search = at + 1;
+                continue;
+            }
+
+            if let Some((len, tag)) = tag_at(&self.line[at..]) {
+                let token = Token {
+                    src: &self.line[at..at + len],
+                    tag: Some(tag),
+                };
+                self.pos = at + len;
+
+                if at == start {
+                    return Some(token);
+                }
+
+                self.next_tag = Some(token);
+                return Some(Token {
+                    src: &self.line[start..at],
+                    tag: None,
+                });
+            }
+
+            search = at + 1;
+        }
+
+        self.pos = self.line.len();
+
+        Some(Token {
+            src: &self.line[start..],
+            tag: None,
+        })
+    }
+}
+
+// Numbered tags starting with @, in order, with the most digits each can take
+const NUMBERED: [(&str, usize); 11] = [
+    (YEAR_AGE, 4),
+    (YEAR_DEATH, 4),
+    (YEAR_BIRTH, 4),
+    (YEAR_OTHER, 4),
+    (TOP_FULL, 2),
+    (TOP, 2),
+    (PER_FULL, 2),
+    (PER, 2),
+    (SRC, 2),
+    (SOC_FULL, 2),
+    (SOC, 2),
+];
+
+// The tag at the very start of the text, if there is one, and its length in bytes
+fn tag_at(text: &str) -> Option<(usize, Tag<'_>)> {
+    let simple =
+        |tag: &str, value: Tag<'static>| text.starts_with(tag).then_some((tag.len(), value));
+
+    match text.as_bytes().first()? {
+        b'P' => page_tag(text),
+        b'%' => simple(HEMI, Tag::Hemistich),
+        b'M' => simple(MILESTONE, Tag::Milestone(None)),
+        b'm' => numbered_milestone(text),
+        b'#' => simple(ROUTE_FROM, Tag::RouteFrom)
+            .or_else(|| simple(ROUTE_TOWA, Tag::RouteTowa))
+            .or_else(|| simple(ROUTE_DIST, Tag::RouteDist)),
+        b'@' => auto_tag(text)
+            .or_else(|| user_tag(text))
+            .or_else(|| simple(MATN, Tag::Matn))
+            .or_else(|| simple(HUKM, Tag::Hukm))
+            .or_else(|| numbered_tag(text)),
+        _ => None,
+    }
+}
+
+// PageV01P002
+fn page_tag(text: &str) -> Option<(usize, Tag<'_>)> {
+    let rest = text.strip_prefix(PAGE)?;
+
+    let vol = ascii_digits(rest, usize::MAX)?;
+    let rest = rest[vol.len()..].strip_prefix('P')?;
+    let page = ascii_digits(rest, usize::MAX)?;
+
+    let len = PAGE.len() + vol.len() + 1 + page.len();
+    Some((len, Tag::Page { vol, page }))
+}
+
+// ms0001, and not the start of a longer word
+fn numbered_milestone(text: &str) -> Option<(usize, Tag<'_>)> {
+    let rest = text.strip_prefix(MILESTONE_NUMBERED)?;
+    let digits = ascii_digits(rest, usize::MAX)?;
+
+    if rest[digits.len()..].starts_with(|c: char| c.is_ascii_alphabetic()) {
+        return None;
+    }
+
+    Some((
+        MILESTONE_NUMBERED.len() + digits.len(),
+        Tag::Milestone(Some(digits)),
+    ))
+}
+
+// @RES@TYPE@Category@, optionally followed by -@fr@
+fn auto_tag(text: &str) -> Option<(usize, Tag<'_>)> {
+    let (resp, remainder) = field(&text[1..], |c| c.is_ascii_uppercase())?;
+    let (t_type, remainder) = field(remainder, |c| c.is_ascii_uppercase())?;
+    let (category, remainder) = field(remainder, |c| c.is_ascii_alphabetic())?;
+
+    if resp.len() != 3 || t_type.len() < 3 {
+        return None;
+    }
+
+    // The review status is two characters, e.g., "fr"
+    let bytes = remainder.as_bytes();
+    let review = match bytes {
+        [b'-', b'@', a, b, b'@', ..] if b"0tf".contains(a) && b"ftalmr".contains(b) => {
+            &remainder[2..4]
+        }
+        _ => "",
+    };
+
+    let mut len = text.len() - remainder.len();
+    if !review.is_empty() {
+        len += 5;
+    }
+
+    Some((
+        len,
+        Tag::Auto {
+            resp,
+            t_type,
+            category,
+            review,
+        },
+    ))
+}
+
+// @USER@CAT_SUBCAT@ or @USER@CAT_SUBCAT_SUBSUBCAT@
+fn user_tag(text: &str) -> Option<(usize, Tag<'_>)> {
+    let (user, rest) = text[1..].split_once('@')?;
+
+    // The other fields can't contain @ or _, and none of them can be empty
+    let end = rest.find('@')?;
+    let mut fields = rest[..end].split('_');
+
+    let t_type = fields.next().filter(|f| !f.is_empty())?;
+    let t_subtype = fields.next().filter(|f| !f.is_empty())?;
+    let t_subsubtype = match fields.next() {
+        Some(f) if !f.is_empty() => f,
+        Some(_) => return None,
+        None => "",
+    };
+
+    if user.is_empty() || fields.next().is_some() {
+        return None;
+    }
+
+    let len = 1 + user.len() + 1 + end + 1;
+
+    Some((
+        len,
+        Tag::User {
+            user,
+            t_type,
+            t_subtype,
+            t_subsubtype,
+        },
+    ))
+}
+
+// Dates, ages, and named entities, e.g., @YD213 or @PER01
+fn numbered_tag(text: &str) -> Option<(usize, Tag<'_>)> {
+    NUMBERED.iter().find_map(|&(prefix, max)| {
+        let digits = ascii_digits(text.strip_prefix(prefix)?, max)?;
+
+        let tag = match prefix {
+            YEAR_AGE => Tag::Age(digits),
+            YEAR_DEATH => Tag::Date(DateType::Death, digits),
+            YEAR_BIRTH => Tag::Date(DateType::Birth, digits),
+            YEAR_OTHER => Tag::Date(DateType::Other, digits),
+            TOP_FULL | TOP => Tag::Entity(EntityType::Top, digits),
+            PER_FULL | PER => Tag::Entity(EntityType::Per, digits),
+            SRC => Tag::Entity(EntityType::Src, digits),
+            _ => Tag::Entity(EntityType::Soc, digits),
+        };
+
+        Some((prefix.len() + digits.len(), tag))
+    })
+}
+
+// The first page number anywhere in the text, for lines the lexer can't make sense of. This is
+// looser than page_tag: the volume is whatever comes before the next P, and the page can have
+// an A or B after it
+pub fn find_page_number(text: &str) -> Option<(&str, &str)> {
+    text.match_indices(PAGE).find_map(|(at, _)| {
+        let (vol, rest) = text[at + PAGE.len()..].split_once('P')?;
+        let digits = ascii_digits(rest, usize::MAX)?;
+
+        let len = digits.len() + usize::from(rest[digits.len()..].starts_with(['A', 'B']));
+
+        (!vol.is_empty()).then(|| (vol, &rest[..len]))
+    })
+}
+
+// Up to max ASCII digits from the start of the text; at least one
+fn ascii_digits(text: &str, max: usize) -> Option<&str> {
+    let len = text
+        .bytes()
+        .take(max)
+        .take_while(u8::is_ascii_digit)
+        .count();
+
+    (len > 0).then(|| &text[..len])
+}
+
+// A non-empty run of characters accepted by the predicate, closed by @. Returns the run and
+// whatever follows the @
+fn field(text: &str, accept: impl Fn(char) -> bool) -> Option<(&str, &str)> {
+    let end = text.find(|c| !accept(c))?;
+
+    if end == 0 || text.as_bytes()[end] != b'@' {
+        return None;
+    }
+
+    Some((&text[..end], &text[end + 1..]))
+}
+
+// The text of a line without any of its tags, trimmed, and with runs of whitespace (which
+// taking out tags tends to leave behind) squeezed into one space. When all the text is in one
+// piece, we can usually hand back a slice of the line
+pub fn text_only<'a>(tokens: &[Token<'a>]) -> Cow<'a, str> {
+    let mut pieces = tokens
+        .iter()
+        .filter(|token| token.tag.is_none() && !token.src.trim().is_empty());
+
+    match (pieces.next(), pieces.next()) {
+        (None, _) => Cow::Borrowed(""),
+        (Some(piece), None) => squeeze(piece.src.trim()),
+        _ => {
+            let joined: String = tokens
+                .iter()
+                .filter(|token| token.tag.is_none())
+                .map(|token| token.src)
+                .collect();
+
+            Cow::Owned(squeeze(joined.trim()).into_owned())
+        }
+    }
+}
+
+pub fn strip_tags(line: &str) -> Cow<'_, str> {
+    let tokens: Vec<Token> = Lexer::new(line).collect();
+    text_only(&tokens)
+}
+
+fn squeeze(text: &str) -> Cow<'_, str> {
+    let mut chars = text.chars().peekable();
+    let mut has_run = false;
+
+    while let Some(c) = chars.next() {
+        if c.is_whitespace() && chars.peek().is_some_and(|next| next.is_whitespace()) {
+            has_run = true;
+            break;
+        }
+    }
+
+    if !has_run {
+        return Cow::Borrowed(text);
+    }
+
+    let mut output = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c.is_whitespace() && chars.peek().is_some_and(|next| next.is_whitespace()) {
+            while chars.next_if(|next| next.is_whitespace()).is_some() {}
+            output.push(' ');
+        } else {
+            output.push(c);
+        }
+    }
+
+    Cow::Owned(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lex(line: &str) -> Vec<(&str, Option<Tag<'_>>)> {
+        Lexer::new(line)
+            .map(|token| (token.src, token.tag))
+            .collect()
+    }
+
+    #[test]
+    fn tokens() {
+        assert_eq!(
+            lex("ابن @PER01 زيد PageV01P002%~%"),
+            [
+                ("ابن ", None),
+                ("@PER01", Some(Tag::Entity(EntityType::Per, "01"))),
+                (" زيد ", None),
+                (
+                    "PageV01P002",
+                    Some(Tag::Page {
+                        vol: "01",
+                        page: "002"
+                    })
+                ),
+                ("%~%", Some(Tag::Hemistich)),
+            ]
+        );
+
+        assert_eq!(
+            lex("@RES@TYPE@Category@-@fr@ @USER@CAT_SUBCAT@"),
+            [
+                (
+                    "@RES@TYPE@Category@-@fr@",
+                    Some(Tag::Auto {
+                        resp: "RES",
+                        t_type: "TYPE",
+                        category: "Category",
+                        review: "fr"
+                    })
+                ),
+                (" ", None),
+                (
+                    "@USER@CAT_SUBCAT@",
+                    Some(Tag::User {
+                        user: "USER",
+                        t_type: "CAT",
+                        t_subtype: "SUBCAT",
+                        t_subsubtype: ""
+                    })
+                ),
+            ]
+        );
+
+        // Not quite tags
+        assert_eq!(
+            lex("@PER PageV01 @U@A__B@"),
+            [("@PER PageV01 @U@A__B@", None)]
+        );
+    }
+
+    #[test]
+    fn precedence() {
+        // User tags come before @MATN@, as they did in the old regex
+        assert_eq!(
+            lex("@MATN@ x_y@")[0].1,
+            Some(Tag::User {
+                user: "MATN",
+                t_type: " x",
+                t_subtype: "y",
+                t_subsubtype: ""
+            })
+        );
+
+        // Entity tags take at most two digits, years four
+        assert_eq!(
+            lex("@P123 @YD12345"),
+            [
+                ("@P12", Some(Tag::Entity(EntityType::Per, "12"))),
+                ("3 ", None),
+                ("@YD1234", Some(Tag::Date(DateType::Death, "1234"))),
+                ("5", None),
+            ]
+        );
+    }
+
+    #[test]
+    fn milestones() {
+        assert_eq!(
+            lex("ابن Milestone300 زيد ms0012، items12 ms3x"),
+            [
+                ("ابن ", None),
+                ("Milestone300", Some(Tag::Milestone(None))),
+                (" زيد ", None),
+                ("ms0012", Some(Tag::Milestone(Some("0012")))),
+                ("، items12 ms3x", None),
+            ]
+        );
+    }
+
+    #[test]
+    fn stripping() {
+        assert_eq!(
+            strip_tags(" ابن  @PER01 زيد\tعمرو Milestone300 "),
+            "ابن زيد\tعمرو"
+        );
+        assert_eq!(strip_tags("a %~% b @YB12"), "a b");
+        assert_eq!(strip_tags("@MATN@"), "");
+    }
+
+    #[test]
+    fn page_numbers() {
+        assert_eq!(
+            find_page_number("x PageVP1 PageV01P002A"),
+            Some(("01", "002A"))
+        );
+        assert_eq!(find_page_number("PageVabcP12B"), Some(("abc", "12B")));
+        assert_eq!(find_page_number("PageV01"), None);
+    }
+}
@@ -7,9 +7,52 @@
     clippy::wildcard_imports
 )]
 
-use anyhow::{anyhow, Result};
-use once_cell::sync::{Lazy, OnceCell};
-use regex::{Captures, Regex};
+use once_cell::sync::OnceCell;
+use regex::Regex;
+use std::borrow::Cow;
+
+mod blocks;
+pub use crate::blocks::*;
+
+mod conll;
+pub use crate::conll::*;
+
+mod corpus;
+pub use crate::corpus::*;
+
+mod entities;
+pub use crate::entities::*;
+
+mod error;
+pub use crate::error::*;
+
+mod import;
+pub use crate::import::*;
+
+mod lexer;
+use crate::lexer::*;
+
+mod metadata;
+pub use crate::metadata::*;
+
+mod milestones;
+pub use crate::milestones::*;
+
+mod pages;
+pub use crate::pages::*;
+
+mod parallel;
+pub use crate::parallel::parse_files;
+use crate::parallel::parse_lines_parallel;
+
+mod sections;
+pub use crate::sections::*;
+
+mod stream;
+pub use crate::stream::*;
+
+mod sidecar;
+pub use crate::sidecar::*;
 
 mod structures;
 pub use crate::structures::*;
@@ -17,6 +60,16 @@ pub use crate::structures::*;
 mod tags;
 use crate::tags::*;
 
+mod tokens;
+pub use crate::tokens::*;
+
+mod uri;
+pub use crate::uri::*;
+
+mod writer;
+use crate::writer::canonical_lines;
+pub use crate::writer::write;
+
 // Regex macro from once_cell
 macro_rules! regex {
     ($re:literal $(,)?) => {{
@@ -25,82 +78,205 @@ macro_rules! regex {
     }};
 }
 
-// This regex needs to be used in two functions, so we define it here
-static PAGE_PATTERN: Lazy<Regex> = Lazy::new(|| Regex::new(r"PageV([^P]+)P(\d+[AB]?)").unwrap());
-
 //
 // HELPER FUNCTIONS
 //
 
-fn split_keep<'a>(re: &Regex, text: &'a str) -> Vec<&'a str> {
-    let mut result = Vec::new();
-    let mut last = 0;
+// When tags are stripped from the start of a line, what's left can still be taken as a slice
+// of the original, which keeps spans exact
+fn as_suffix<'a>(line: &'a str, stripped: &'a str) -> &'a str {
+    if line.ends_with(stripped) {
+        &line[line.len() - stripped.len()..]
+    } else {
+        stripped
+    }
+}
+
+// Named-entity tags carry two digits, prefix and extent. Anything else is malformed
+fn entity_digits(val: &str) -> Option<(u32, u32)> {
+    let mut iter = val.chars();
+
+    let prefix = iter.next()?.to_digit(10)?;
+    let extent = iter.next()?.to_digit(10)?;
+
+    Some((prefix, extent))
+}
+
+// Split the prefix off the words of a named entity: that many characters from the start of
+// the first word, plus any vowels on the last of them (وَ is one character, as far as
+// mARkdown is concerned). None if that would leave nothing of the first word
+fn split_entity_prefix(entity: &str, prefix: u32) -> Option<(&str, &str)> {
+    let first_word = entity.split(' ').next().unwrap_or_default();
+    let mut chars = first_word.char_indices().peekable();
+
+    for _ in 0..prefix {
+        chars.next()?;
+
+        while chars.next_if(|&(_, c)| is_diacritic(c)).is_some() {}
+    }
+
+    let split_at = chars.peek()?.0;
+    Some(entity.split_at(split_at))
+}
+
+// Short vowels, tanwin, shadda, sukun, and dagger alif
+const fn is_diacritic(c: char) -> bool {
+    matches!(c, '\u{064B}'..='\u{0652}' | '\u{0670}')
+}
+
+// Byte offset of a slice within the string it was taken from, if it was
+fn offset_in(outer: &str, inner: &str) -> Option<usize> {
+    let start = outer.as_ptr() as usize;
+    let ptr = inner.as_ptr() as usize;
+
+    if ptr >= start && ptr + inner.len() <= start + outer.len() {
+        Some(ptr - start)
+    } else {
+        None
+    }
+}
+
+// Join a line, or a piece of one, onto plain text being put together (of a page, or a
+// milestone chunk)
+fn push_text(out: &mut String, text: &str) {
+    let text = text.trim();
+    if text.is_empty() {
+        return;
+    }
+
+    if !out.is_empty() {
+        out.push(' ');
+    }
+
+    out.push_str(text);
+}
+
+// Where the physical line being parsed sits in the input, for spans and error reporting
+#[derive(Clone, Copy)]
+struct LineCtx<'a> {
+    number: usize,
+    start: usize,
+    raw: &'a str,
+}
+
+impl<'a> LineCtx<'a> {
+    // Byte offset of a fragment within the raw line, if it's a slice of it. Text that had
+    // to be put together (e.g., with tags taken out of the middle) isn't looked for, since
+    // the same words may well be in the line more than once
+    fn locate(&self, fragment: &str) -> Option<usize> {
+        offset_in(self.raw, fragment)
+    }
+
+    // Text for the document tree. Wherever it appears verbatim in the raw line, we point to
+    // it there rather than making a copy
+    fn text(&self, fragment: &str) -> Cow<'a, str> {
+        match self.locate(fragment) {
+            Some(start) => Cow::Borrowed(&self.raw[start..start + fragment.len()]),
+            None => Cow::Owned(fragment.into()),
+        }
+    }
 
-    // Is it safe that this function works based on the byte length of strings?
-    // I guess it's probably ok in this context
+    // Text that isn't in the line as it is (e.g., with tags taken out of the middle) gets
+    // the span of the whole line, which at least contains it
+    fn span_of(&self, fragment: &str) -> Span {
+        let (offset, len) = self
+            .locate(fragment)
+            .map_or((0, self.raw.len()), |offset| (offset, fragment.len()));
+
+        Span {
+            line: self.number,
+            start: self.start + offset,
+            end: self.start + offset + len,
+        }
+    }
 
-    for (index, separator) in text.match_indices(re) {
-        // Ok, we matched on a separator
-        // First add to the result the text leading up to the separator
-        // i.e., the text since "last"
-        if last != index {
-            result.push(&text[last..index]);
+    fn warning(&self, kind: DiagnosticKind, fragment: &str, message: String) -> Diagnostic {
+        Diagnostic {
+            kind,
+            span: self.span_of(fragment),
+            message,
         }
+    }
+
+    fn page_warning(&self, tag: &str) -> Diagnostic {
+        self.warning(
+            DiagnosticKind::MalformedPageTag,
+            tag,
+            format!("malformed page tag `{tag}`"),
+        )
+    }
+
+    fn entity_warning(&self, tag: &str) -> Diagnostic {
+        self.warning(
+            DiagnosticKind::MalformedEntityTag,
+            tag,
+            format!("malformed named-entity tag `{tag}`"),
+        )
+    }
+}
 
-        // Also add to the result the separator itself
-        // And update "last" to just after the separator (I think)
-        result.push(separator);
-        last = index + separator.len();
+// Classification of block-level lines. These are shared with the writer, which needs to
+// know which marker will be read back as which type
+
+pub(crate) fn header_level(line: &str) -> u32 {
+    if line.contains(HEADER5) {
+        5
+    } else if line.contains(HEADER4) {
+        4
+    } else if line.contains(HEADER3) {
+        3
+    } else if line.contains(HEADER2) {
+        2
+    } else {
+        1
     }
+}
 
-    // If some text remains between the end of the last separator and the end of the string,
-    // also add that to the result
-    if last < text.len() {
-        result.push(&text[last..]);
+pub(crate) fn dic_type(line: &str) -> DicType {
+    if line.contains(DIC_LEX) {
+        DicType::Lex
+    } else if line.contains(DIC_NIS) {
+        DicType::Nis
+    } else if line.contains(DIC_TOP) {
+        DicType::Top
+    } else {
+        DicType::Bib
     }
+}
 
-    result
+pub(crate) fn dox_type(line: &str) -> DoxType {
+    if line.contains(DOX_SEC) {
+        DoxType::Sec
+    } else {
+        DoxType::Pos
+    }
 }
 
-fn remove_phrase_lv_tags(line: String) -> String {
-    let mut text_only = line;
+// I think "value" means the actual heading content, minus the tag
+pub(crate) fn header_value(line: &str) -> String {
+    let mut value = line.to_owned();
 
-    // First strip tags that don't involve regex
-    for tag in PHRASE_LV_TAGS {
-        text_only = text_only.replace(tag, "");
+    for tag in HEADERS {
+        value = value.replace(tag, "");
     }
 
-    // Then use this nasty regex to strip the remainder
-
-    let yikes = regex!(
-        r"(?x)
-        (
-            @YA\d{1,4}     | # Year 1 (age)
-            @YD\d{1,4}     | # Year 2 (death)
-            @YB\d{1,4}     | # Year 3 (birth)
-            @YY\d{1,4}     | # Year 4 (other)
-            @TOP\d{1,2}    | # Topological full
-            @T\d{1,2}      | # Topological
-            @PER\d{1,2}    | # Person full
-            @P\d{1,2}      | # Person
-            @SRC\d{1,2}    | # Source (?)
-            @SOC\d{1,2}    | # SOC full (?)
-            @S\d{1,2}      | # SOC (?)
-            @([^@]+?)@([^_@]+?)_([^_@]+?)(_([^_@]+?))?@               | # OPEN_TAG_CUSTOM_GRP
-            @([A-Z]{3})@([A-Z]{3,})@([A-Za-z]+)@(-@([0tf][ftalmr])@)? | # OPEN_TAG_AUTO_GRP
-            PageV(\d+)P(\d+) # Page pattern
-        )"
-    );
-
-    // We can also trim outside whitespace before returning
-    text_only = yikes.replace_all(&text_only, "").trim().into();
-
-    // Replace any occurrence of multiple spaces with one space
-    // This comes up because of tag removal and was annoying me
-    let multiple_spaces = regex!(r"\s{2,}");
-    text_only = multiple_spaces.replace_all(&text_only, " ").into();
-
-    text_only
+    strip_tags(&value).into_owned()
+}
+
+pub(crate) fn be_type(line: &str) -> BeType {
+    if line.contains(LIST_NAMES_FULL) || line.contains(LIST_NAMES) {
+        BeType::Names
+    } else if line.contains(BIO_REF_FULL) || line.contains(BIO_REF) {
+        BeType::Ref
+    } else if line.contains(BIO_WOM_FULL) || line.contains(BIO_WOM) {
+        BeType::Wom
+    } else if line.contains(LIST_EVENTS) {
+        BeType::Events
+    } else if line.contains(EVENT) {
+        BeType::Event
+    } else {
+        BeType::Man
+    }
 }
 
 //
@@ -108,19 +284,20 @@ fn remove_phrase_lv_tags(line: String) -> String {
 //
 
 #[allow(clippy::too_many_lines)]
-fn parse_line(tagged_line: &str, kind: Option<LineType>, first_token: bool) -> Option<Line> {
+fn parse_line<'a>(
+    tagged_line: &str,
+    kind: Option<LineType>,
+    first_token: bool,
+    ctx: LineCtx<'a>,
+    open_entity: &mut Option<OpenEntity>,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> Option<Line<'a>> {
     // Remove initial line marker
     let line = tagged_line.trim_start_matches(LINE);
 
     // Remove phrase-level tags (whatever that means)
-    let without_tags = remove_phrase_lv_tags(line.to_owned());
-
-    // This was weird: the Python library returns None here if there's nothing left
-    // after stripping tags from the line. But that caused a problem for lines where
-    // there's a page number tag (which we do want to parse) and nothing else
-    if without_tags.is_empty() && !line.contains(PAGE) {
-        return None;
-    }
+    let tokens: Vec<Token> = Lexer::new(line).collect();
+    let without_tags = text_only(&tokens);
 
     // Create vec for line parts
     let mut parts: Vec<LinePart> = Vec::new();
@@ -130,353 +307,264 @@ fn parse_line(tagged_line: &str, kind: Option<LineType>, first_token: bool) -> O
     // is Isnad. So I made the function argument into a bool for simplicity.
     // But I still don't understand what this is supposed to accomplish...
     if first_token {
-        parts.push(LinePart::Isnad);
+        let span = ctx.span_of(&line[..0]);
+        parts.push(LinePart::Isnad { span });
     }
 
-    // Regex sadness
-
-    let ungodly = regex!(
-        r"(?x)
-        (
-            PageV\d+P\d+ | # Page number
-            @[A-Z]{3}@[A-Z]{3,}@[A-Za-z]+@(?:-@[0tf][ftalmr]@)? | # OPEN_TAG_AUTO
-            @[^@]+?@[^_@]+?_[^_@]+?(?:_[^_@]+?)?@               | # OPEN_TAG_CUSTOM
-            %~%          | # HEMI
-            Milestone300 | # MILESTONE
-            @MATN@       | # MATN
-            @HUKM@       | # HUKM
-            \#\$\#FROM   | # ROUTE_FROM
-            \#\$\#TOWA   | # ROUTE_TOWA
-            \#\$\#DIST   | # ROUTE_DIST
-            @YA\d{1,4}   | # Year 1 (age)
-            @YD\d{1,4}   | # Year 2 (death)
-            @YB\d{1,4}   | # Year 3 (birth)
-            @YY\d{1,4}   | # Year 4 (other)
-            @TOP\d{1,2}  | # Topological full (?)
-            @T\d{1,2}    | # Topological (?)
-            @PER\d{1,2}  | # Person full (?)
-            @P\d{1,2}    | # Person (?)
-            @SRC\d{1,2}  | # Source (?)
-            @SOC\d{1,2}  | # SOC full (?)
-            @S\d{1,2}      # SOC (?)
-        )"
-    );
-
-    // Split the line on tokens using the mega-regex, while keeping the tokens
-    // This requires a custom function in Rust, unlike in Python
-    let tokens = split_keep(ungodly, line);
-
-    // More regex for the upcoming loop
-
-    let open_tag_custom_pattern_grouped = regex!("^@([^@]+?)@([^_@]+?)_([^_@]+?)(_([^_@]+?))?@");
-    let open_tag_auto_pattern_grouped =
-        regex!("^@([A-Z]{3})@([A-Z]{3,})@([A-Za-z]+)@(-@([0tf][ftalmr])@)?");
-
     // When we come upon a tag for a "named entity," we can set this variable to indicate how
     // many of the *following* words (i.e., how much of the next token, I guess) to set aside
     // as the text of that entity
     // I can't make it work in Rust quite like it does in Python, though
     let mut include_words: u32 = 0;
 
+    // And how many characters at the start of those words are a prefix (a conjunction,
+    // preposition, or article) that's not part of the entity itself, e.g., و and ب in وبغداد
+    let mut include_prefix: u32 = 0;
+
     // Given the changes I've made, I also want to indicate entity type for NamedEntityText
     let mut entity_type: Option<EntityType> = None;
 
+    // An entity from the line before may not be done yet
+    if let Some(open) = open_entity.take() {
+        include_words = open.words;
+        include_prefix = open.prefix;
+        entity_type = Some(open.ne_type);
+    }
+
     // Iterate over line tokens
     // Basically, a token could be a tag, or any text falling between two tags
-    // We've already split the line on tags as separators, while keeping those tags
+    // The lexer has already split the line on tags, and picked the tags apart
     for token in tokens {
-        // Again, let's start by trimming whitespace, and use this version henceforth
-        // This is not done in the Python library, but I prefer it
-        let token_trimmed = token.trim();
-
-        // I guess this would mean a content-less line? How would that happen at this point?
-        if token_trimmed.is_empty() {
-            continue;
-        }
-
-        // Capture "open tag custom" or "open tag auto" (whatever that means)
-
-        let mut opentag_captures: Option<Captures> = None;
-        let mut opentagauto_captures: Option<Captures> = None;
-
-        if token_trimmed.starts_with('@') {
-            opentag_captures = open_tag_custom_pattern_grouped.captures(token_trimmed);
-            opentagauto_captures = open_tag_auto_pattern_grouped.captures(token_trimmed);
-        }
-
-        // Here begin the if/else branches that take up the rest of the function
-
-        // Page number
-        if token_trimmed.contains(PAGE) {
-            let page_captures = PAGE_PATTERN.captures(token_trimmed);
-
-            if let Some(page_matches) = page_captures {
-                let vol = page_matches[1].into();
-                let page = page_matches[2].into();
-
-                parts.push(LinePart::PageNumber(PageNumber { vol, page }));
-            } else {
-                // An exception is raised here in the Python library; I haven't done anything
-            }
-        // "Open tag custom" (?)
-        } else if let Some(opentag_matches) = opentag_captures {
-            let user = opentag_matches[1].into();
-            let t_type = opentag_matches[2].into();
-            let t_subtype = opentag_matches[3].into();
-            let t_subsubtype = opentag_matches[5].into();
-
-            parts.push(LinePart::OpenTagUser {
+        let span = ctx.span_of(token.src);
+
+        let part = match token.tag {
+            // Page number
+            Some(Tag::Page { vol, page }) => LinePart::PageNumber(PageNumber {
+                vol: ctx.text(vol),
+                page: ctx.text(page),
+                span,
+            }),
+            // "Open tag custom" (?)
+            Some(Tag::User {
                 user,
                 t_type,
                 t_subtype,
                 t_subsubtype,
-            });
-        // "Open tag auto" (?)
-        } else if let Some(opentagauto_matches) = opentagauto_captures {
-            let resp = opentagauto_matches[1].into();
-            let t_type = opentagauto_matches[2].into();
-            let category = opentagauto_matches[3].into();
-            let review = opentagauto_matches[5].into();
-
-            parts.push(LinePart::OpenTagAuto {
+            }) => LinePart::OpenTagUser {
+                user: ctx.text(user),
+                t_type: ctx.text(t_type),
+                t_subtype: ctx.text(t_subtype),
+                t_subsubtype: ctx.text(t_subsubtype),
+                span,
+            },
+            // "Open tag auto" (?)
+            Some(Tag::Auto {
                 resp,
                 t_type,
                 category,
                 review,
-            });
-        // Hemistich
-        } else if token_trimmed.contains(HEMI) {
-            parts.push(LinePart::Hemistich {
-                orig: token_trimmed.into(),
-            });
-        // "Milestone" (used to break up texts into manageable units)
-        } else if token_trimmed.contains(MILESTONE) {
-            parts.push(LinePart::Milestone);
-        // Matn (?)
-        } else if token_trimmed.contains(MATN) {
-            parts.push(LinePart::Matn);
-        // Ḥukm (?)
-        } else if token_trimmed.contains(HUKM) {
-            parts.push(LinePart::Hukm);
-        // Route from
-        } else if token_trimmed.contains(ROUTE_FROM) {
-            parts.push(LinePart::RouteFrom);
-        // Route toward
-        } else if token_trimmed.contains(ROUTE_TOWA) {
-            parts.push(LinePart::RouteTowa);
-        // Route distance (?)
-        } else if token_trimmed.contains(ROUTE_DIST) {
-            parts.push(LinePart::RouteDist);
-        // Year of birth
-        } else if token_trimmed.contains(YEAR_BIRTH) {
-            let value = token_trimmed.trim_start_matches(YEAR_BIRTH).into();
-            let date_type = DateType::Birth;
-
-            parts.push(LinePart::Date { value, date_type });
-        // Year of death
-        } else if token_trimmed.contains(YEAR_DEATH) {
-            let value = token_trimmed.trim_start_matches(YEAR_DEATH).into();
-            let date_type = DateType::Death;
-
-            parts.push(LinePart::Date { value, date_type });
-        // Other year
-        } else if token_trimmed.contains(YEAR_OTHER) {
-            let value = token_trimmed.trim_start_matches(YEAR_OTHER).into();
-            let date_type = DateType::Other;
-
-            parts.push(LinePart::Date { value, date_type });
-        // Age
-        } else if token_trimmed.contains(YEAR_AGE) {
-            let value = token_trimmed.trim_start_matches(YEAR_AGE).into();
-            parts.push(LinePart::Age { value });
-        // Source
-        } else if token_trimmed.contains(SRC) {
-            // This should yield a string representation of a two-digit number
-            let val = token_trimmed.trim_start_matches(SRC);
-            let mut iter = val.chars();
-
-            // Now make an int out of each char. This is so f'ing janky
-            let prefix_char = iter.next().unwrap();
-            let prefix: u32 = prefix_char.to_digit(10).unwrap();
-
-            let extent_char = iter.next().unwrap();
-            let extent: u32 = extent_char.to_digit(10).unwrap();
-
-            // I guess this is the number of words to put into this iteration's
-            // text field in the next iteration? Yikes
-            // Literally can't figure out how to do that in Rust
-            // I'm taking a different approach: the subsequent LinePart will be
-            // NamedEntityText
-            include_words = extent;
-            entity_type = Some(EntityType::Src);
-
-            parts.push(LinePart::NamedEntity {
-                prefix,
-                extent,
-                ne_type: EntityType::Src,
-            });
-        // Not sure what SOC means
-        } else if token_trimmed.starts_with(SOC_FULL) {
-            let val = token_trimmed.trim_start_matches(SOC_FULL);
-            let mut iter = val.chars();
-
-            let prefix_char = iter.next().unwrap();
-            let prefix: u32 = prefix_char.to_digit(10).unwrap();
-
-            let extent_char = iter.next().unwrap();
-            let extent: u32 = extent_char.to_digit(10).unwrap();
-
-            include_words = extent;
-            entity_type = Some(EntityType::Soc);
-
-            parts.push(LinePart::NamedEntity {
-                prefix,
-                extent,
-                ne_type: EntityType::Soc,
-            });
-        // Again SOC...
-        } else if token_trimmed.starts_with(SOC) {
-            let val = token_trimmed.trim_start_matches(SOC);
-            let mut iter = val.chars();
-
-            let prefix_char = iter.next().unwrap();
-            let prefix: u32 = prefix_char.to_digit(10).unwrap();
-
-            let extent_char = iter.next().unwrap();
-            let extent: u32 = extent_char.to_digit(10).unwrap();
-
-            include_words = extent;
-            entity_type = Some(EntityType::Soc);
-
-            parts.push(LinePart::NamedEntity {
-                prefix,
-                extent,
-                ne_type: EntityType::Soc,
-            });
-        // Topological entity (I think)
-        } else if token_trimmed.starts_with(TOP_FULL) {
-            let val = token_trimmed.trim_start_matches(TOP_FULL);
-            let mut iter = val.chars();
-
-            let prefix_char = iter.next().unwrap();
-            let prefix: u32 = prefix_char.to_digit(10).unwrap();
-
-            let extent_char = iter.next().unwrap();
-            let extent: u32 = extent_char.to_digit(10).unwrap();
-
-            include_words = extent;
-            entity_type = Some(EntityType::Top);
-
-            parts.push(LinePart::NamedEntity {
-                prefix,
-                extent,
-                ne_type: EntityType::Top,
-            });
-        // Again topological entity
-        } else if token_trimmed.starts_with(TOP) {
-            let val = token_trimmed.trim_start_matches(TOP);
-            let mut iter = val.chars();
-
-            let prefix_char = iter.next().unwrap();
-            let prefix: u32 = prefix_char.to_digit(10).unwrap();
-
-            let extent_char = iter.next().unwrap();
-            let extent: u32 = extent_char.to_digit(10).unwrap();
-
-            include_words = extent;
-            entity_type = Some(EntityType::Top);
-
-            parts.push(LinePart::NamedEntity {
-                prefix,
-                extent,
-                ne_type: EntityType::Top,
-            });
-        // Person (?)
-        } else if token_trimmed.starts_with(PER_FULL) {
-            let val = token_trimmed.trim_start_matches(PER_FULL);
-            let mut iter = val.chars();
-
-            let prefix_char = iter.next().unwrap();
-            let prefix: u32 = prefix_char.to_digit(10).unwrap();
-
-            let extent_char = iter.next().unwrap();
-            let extent: u32 = extent_char.to_digit(10).unwrap();
-
-            include_words = extent;
-            entity_type = Some(EntityType::Per);
-
-            parts.push(LinePart::NamedEntity {
-                prefix,
-                extent,
-                ne_type: EntityType::Per,
-            });
-        // Again person
-        } else if token_trimmed.starts_with(PER) {
-            let val = token_trimmed.trim_start_matches(PER);
-            let mut iter = val.chars();
+            }) => LinePart::OpenTagAuto {
+                resp: ctx.text(resp),
+                t_type: ctx.text(t_type),
+                category: ctx.text(category),
+                review: ctx.text(review),
+                span,
+            },
+            Some(Tag::Hemistich) => LinePart::Hemistich {
+                orig: ctx.text(token.src),
+                span,
+            },
+            // "Milestone" (used to break up texts into manageable units)
+            Some(Tag::Milestone(number)) => LinePart::Milestone {
+                number: number.map(|number| ctx.text(number)),
+                span,
+            },
+            // Matn (?)
+            Some(Tag::Matn) => LinePart::Matn { span },
+            // Ḥukm (?)
+            Some(Tag::Hukm) => LinePart::Hukm { span },
+            Some(Tag::RouteFrom) => LinePart::RouteFrom { span },
+            Some(Tag::RouteTowa) => LinePart::RouteTowa { span },
+            // Route distance (?)
+            Some(Tag::RouteDist) => LinePart::RouteDist { span },
+            // Years of birth, death, etc.
+            Some(Tag::Date(date_type, value)) => LinePart::Date {
+                value: ctx.text(value),
+                date_type,
+                span,
+            },
+            Some(Tag::Age(value)) => LinePart::Age {
+                value: ctx.text(value),
+                span,
+            },
+            // Person, place, etc.
+            Some(Tag::Entity(ne_type, val)) => {
+                // Now make an int out of each char. This is so f'ing janky
+                // Without both digits, it's kept as text
+                let Some((prefix, extent)) = entity_digits(val) else {
+                    diagnostics.push(ctx.entity_warning(token.src));
+                    parts.push(LinePart::TextPart {
+                        text: ctx.text(token.src),
+                        span,
+                    });
+                    continue;
+                };
 
-            let prefix_char = iter.next().unwrap();
-            let prefix: u32 = prefix_char.to_digit(10).unwrap();
+                // I guess this is the number of words to put into this iteration's
+                // text field in the next iteration? Yikes
+                // Literally can't figure out how to do that in Rust
+                // I'm taking a different approach: the subsequent LinePart will be
+                // NamedEntityText
+                include_words = extent;
+                include_prefix = prefix;
+                entity_type = Some(ne_type);
+
+                LinePart::NamedEntity {
+                    prefix,
+                    extent,
+                    ne_type,
+                    span,
+                }
+            }
+            None => {
+                // Again, let's start by trimming whitespace, and use this version henceforth
+                // This is not done in the Python library, but I prefer it
+                let text = token.src.trim();
+
+                // Nothing but whitespace between two tags
+                if text.is_empty() {
+                    continue;
+                }
 
-            let extent_char = iter.next().unwrap();
-            let extent: u32 = extent_char.to_digit(10).unwrap();
+                let span = ctx.span_of(text);
 
-            include_words = extent;
-            entity_type = Some(EntityType::Per);
+                // A named-entity tag without its digits (this covers @SRC, @PER, etc.). We
+                // make a note of it, and the text is dealt with like any other
+                if [SOC, TOP, PER].iter().any(|tag| text.starts_with(tag)) {
+                    let tag = text.split(' ').next().unwrap_or(text);
+                    diagnostics.push(ctx.entity_warning(tag));
+                }
 
-            parts.push(LinePart::NamedEntity {
-                prefix,
-                extent,
-                ne_type: EntityType::Per,
-            });
-        } else if include_words > 0 {
-            // This block becomes active if we assigned a new value to include_words
-            // That would mean that there is some NamedEntity that has been added
-            // The idea, again, is that we take a number of words *after* the tag introducing
-            // the NamedEntity, and, in the following iteration, add those words as the
-            // text field of the NamedEntity. I don't see how this can be done in Rust with
-            // static typing, the borrow checker, etc.
-            // So I gave up and changed how this works. We instead add a NamedEntityText object
-            // that should occur just after the NamedEntity object. And we can still capture the
-            // correct number of words.
-            let mut entity = String::new();
-            let mut remainder = String::new();
-
-            let words: Vec<&str> = token_trimmed.split(' ').collect();
-
-            for (pos, word) in words.iter().enumerate() {
-                if pos < include_words as usize {
-                    entity.push_str(word);
-                    entity.push(' ');
+                if text.contains(PAGE) {
+                    // Something like a page number, but not enough like one for the lexer
+                    if let Some((vol, page)) = find_page_number(text) {
+                        LinePart::PageNumber(PageNumber {
+                            vol: ctx.text(vol),
+                            page: ctx.text(page),
+                            span,
+                        })
+                    } else {
+                        // An exception is raised here in the Python library; we just make a
+                        // note of it and hang on to the text
+                        diagnostics.push(ctx.page_warning(text));
+
+                        LinePart::TextPart {
+                            text: ctx.text(text),
+                            span,
+                        }
+                    }
+                } else if include_words > 0 {
+                    // This block becomes active if we assigned a new value to include_words
+                    // That would mean that there is some NamedEntity that has been added
+                    // The idea, again, is that we take a number of words *after* the tag
+                    // introducing the NamedEntity, and, in the following iteration, add those
+                    // words as the text field of the NamedEntity. I don't see how this can be
+                    // done in Rust with static typing, the borrow checker, etc.
+                    // So I gave up and changed how this works. We instead add a
+                    // NamedEntityText object that should occur just after the NamedEntity
+                    // object. And we can still capture the correct number of words.
+                    let mut entity = String::new();
+                    let mut remainder = String::new();
+
+                    let words: Vec<&str> = text.split(' ').collect();
+
+                    for (pos, word) in words.iter().enumerate() {
+                        if pos < include_words as usize {
+                            entity.push_str(word);
+                            entity.push(' ');
+                        } else {
+                            remainder.push_str(word);
+                            remainder.push(' ');
+                        }
+                    }
+
+                    // Work out where in the token the entity text stops, so each part gets
+                    // its own span
+                    let split_at = words
+                        .get(include_words as usize)
+                        .and_then(|word| offset_in(text, word))
+                        .unwrap_or(text.len());
+                    let (entity_src, remainder_src) = text.split_at(split_at);
+
+                    if let (false, Some(ne_type)) = (entity.is_empty(), entity_type) {
+                        let entity_src = entity_src.trim();
+                        let split = split_entity_prefix(entity_src, include_prefix);
+
+                        if split.is_none() {
+                            diagnostics.push(ctx.warning(
+                                DiagnosticKind::MalformedEntityPrefix,
+                                entity_src,
+                                format!("entity prefix of {include_prefix} characters leaves nothing of `{entity_src}`"),
+                            ));
+                        }
+
+                        // Without a prefix, then
+                        let (prefix, entity_text) = split.unwrap_or((&entity_src[..0], entity_src));
+
+                        parts.push(LinePart::NamedEntityText {
+                            prefix: ctx.text(prefix),
+                            text: ctx.text(entity_text),
+                            ne_type,
+                            span: ctx.span_of(entity_text),
+                        });
+                    }
+
+                    if !remainder.is_empty() {
+                        parts.push(LinePart::TextPart {
+                            text: ctx.text(remainder_src.trim()),
+                            span: ctx.span_of(remainder_src.trim()),
+                        });
+                    }
+
+                    // Whatever words are left over come from the next piece of text, which
+                    // may be past a page marker, or on the next line. The prefix can only be
+                    // on the first word, though
+                    include_words = include_words
+                        .saturating_sub(u32::try_from(words.len()).unwrap_or(u32::MAX));
+                    include_prefix = 0;
+                    if include_words == 0 {
+                        entity_type = None;
+                    }
+
+                    continue;
                 } else {
-                    remainder.push_str(word);
-                    remainder.push(' ');
+                    // If we made it to this point and no tag or anything else matched,
+                    // we can just add it to the line as textual content
+                    LinePart::TextPart {
+                        text: ctx.text(text),
+                        span,
+                    }
                 }
             }
+        };
 
-            if !entity.is_empty() {
-                parts.push(LinePart::NamedEntityText {
-                    text: entity.trim().into(),
-                    ne_type: entity_type.unwrap(),
-                });
-            }
+        parts.push(part);
+    }
 
-            if !remainder.is_empty() {
-                parts.push(LinePart::TextPart {
-                    text: remainder.trim().into(),
-                });
-            }
+    if let (true, Some(ne_type)) = (include_words > 0, entity_type) {
+        *open_entity = Some(OpenEntity {
+            ne_type,
+            words: include_words,
+            prefix: include_prefix,
+        });
+    }
 
-            // Reset include_words to 0, entity_type to None
-            include_words = 0;
-            entity_type = None;
-        } else {
-            // If we made it to this point and no tag or anything else matched,
-            // we can just add it to the line as textual content
-            parts.push(LinePart::TextPart {
-                text: token.trim().into(),
-            });
-        }
+    // The Python library returns None if there's no text left after stripping tags from the
+    // line. But then a line with nothing but tags (a milestone, say) would be lost, so we only
+    // do that when there's nothing at all. A `~~` line is kept even then, since there's
+    // nowhere else for it to go
+    if parts.iter().all(LinePart::is_isnad) && !tagged_line.starts_with(LINE) {
+        return None;
     }
 
     // Set up return value
@@ -493,7 +581,11 @@ fn parse_line(tagged_line: &str, kind: Option<LineType>, first_token: bool) -> O
     let text_only = if without_tags.is_empty() {
         None
     } else {
-        Some(without_tags)
+        // Don't go looking for text that had to be put together; it won't be in the source
+        match without_tags {
+            Cow::Borrowed(text) => Some(ctx.text(text)),
+            Cow::Owned(text) => Some(Cow::Owned(text)),
+        }
     };
 
     // I've tried to match the Python library here, in particular using the
@@ -502,6 +594,7 @@ fn parse_line(tagged_line: &str, kind: Option<LineType>, first_token: bool) -> O
         text_only,
         parts,
         line_type,
+        span: ctx.span_of(tagged_line),
     };
 
     Some(line_struct)
@@ -511,266 +604,465 @@ fn parse_line(tagged_line: &str, kind: Option<LineType>, first_token: bool) -> O
 // MAIN PARSER FUNCTION
 //
 
+/// Options for [`parser_with_options`]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ParseOptions {
+    /// Keep the raw source lines in [`Document::trivia`], so that [`write`] can reproduce
+    /// the input byte for byte (apart from whatever has been edited in the meantime)
+    pub lossless: bool,
+    /// Parse the body of the document in chunks, on rayon's thread pool. The result is the
+    /// same either way; this only pays off for large files
+    pub parallel: bool,
+    /// Fail on the first malformed page tag, malformed named-entity tag, or bad metadata
+    /// line (see [`ParseError`]), instead of noting it in the diagnostics and going on
+    pub strict: bool,
+}
+
 /// # Errors
 ///
-/// Will return an error if the input text appears not to be an OpenITI mARkdown document.
+/// Will return an error if the input text appears not to be an OpenITI mARkdown document (see
+/// [`ParseError`]). Anything malformed after that is kept, and noted in the diagnostics;
+/// for an error instead, use strict mode (see [`ParseOptions`]).
+pub fn parser(input: &str) -> Result<Document<'_>, ParseError> {
+    parser_with_options(input, ParseOptions::default())
+}
+
+// Whatever needs to be remembered from one line to the next
+#[derive(Clone, Copy, Debug, Default)]
+struct ParserState {
+    // Metadata is only supposed to appear in the header
+    header_end: Option<usize>,
+    // A named entity with words still to come, when the line broke in the middle of it
+    entity: Option<OpenEntity>,
+}
+
+// The rest of a named entity, to be taken from the next line
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct OpenEntity {
+    ne_type: EntityType,
+    words: u32,
+    // Still to be split off, if none of the entity's words were on the line with the tag
+    prefix: u32,
+}
+
+// The body of the main loop: parse one physical line of the source into events. This is
+// shared between parser and the streaming StreamParser, so that they can't drift apart
 #[allow(clippy::too_many_lines)]
-pub fn parser(input: &str) -> Result<Document> {
-    // This is our return value, gods willing
-    let mut doc = Document {
-        magic_value: String::new(),
-        simple_metadata: Vec::new(),
-        content: Vec::new(),
-    };
+fn parse_source_line<'a>(
+    ctx: LineCtx<'a>,
+    state: &mut ParserState,
+    events: &mut Vec<Event<'a>>,
+) -> Result<(), ParseError> {
+    let mut content = Vec::new();
+    let mut diagnostics = Vec::new();
 
     // Regexes. It would probably be ok to skip the once_cell approach here, but whatever
     let morpho_pattern = regex!("#~:([^:]+?):");
     let para_pattern = regex!("^#($|[^#])");
     let bio_pattern = regex!(r"### \$[^#]");
     let region_pattern =
-        regex!(r"(#\$#PROV|#\$#REG\d) .*? #\$#TYPE .*? (#\$#REG\d|#\$#STTL) ([\w# ]+) $");
-
-    // Main loop
-    for (i, line) in input.lines().enumerate() {
-        // Start by trimming whitespace. This version is all we'll use henceforth
-        let line_trimmed = line.trim();
-
-        // Check for magic value
-        if i == 0 && line_trimmed.starts_with("######OpenITI#") {
-            doc.magic_value = line_trimmed.into();
-
-            // Need to specify continue here; but everything that follows is if/else
-            continue;
-        } else if i == 0 {
-            // If it's the first line and doesn't start with the magic value, abort
-            return Err(anyhow!(
-                "This does not appear to be an OpenITI mARkdown document"
+        regex!(r"^(#\$#PROV|#\$#REG\d) .*? #\$#TYPE .*? (#\$#REG\d|#\$#STTL) ([\w# ]+)$");
+
+    // Start by trimming whitespace. This version is all we'll use henceforth
+    let line_trimmed = ctx.raw.trim();
+    let span = ctx.span_of(line_trimmed);
+
+    // A named entity only runs on into a `~~` line (or past a page marker on its own line).
+    // Anything else starts afresh
+    if !(line_trimmed.starts_with(LINE) || line_trimmed.starts_with(PAGE)) {
+        state.entity = None;
+    }
+
+    // Check for magic value
+    if ctx.number == 1 && line_trimmed.starts_with("######OpenITI#") {
+        events.push(Event::Magic(line_trimmed.into()));
+
+        // Need to return early here; but everything that follows is if/else
+        return Ok(());
+    } else if ctx.number == 1 {
+        // If it's the first line and doesn't start with the magic value, abort
+        return Err(ParseError::BadMagic { line: 1, column: 1 });
+    }
+
+    // Non-machine-readable metadata
+    if line_trimmed.starts_with(META) {
+        // I guess the metadata ending tag gets dropped in parsing
+        if line_trimmed == META_END {
+            state.header_end = state.header_end.or(Some(ctx.number));
+            return Ok(());
+        }
+
+        // We keep stray metadata, but flag it
+        if state.header_end.is_some() {
+            diagnostics.push(ctx.warning(
+                DiagnosticKind::MetadataAfterHeader,
+                line_trimmed,
+                "metadata line after the end of the header".into(),
             ));
         }
 
-        // Non-machine-readable metadata
-        if line_trimmed.starts_with(META) {
-            // I guess the metadata ending tag gets dropped in parsing
-            if line_trimmed == META_END {
-                continue;
-            }
+        // Much trimming!
+        let value = line_trimmed.trim_start_matches(META).trim();
+
+        // Every metadata line should at least be a key-value pair. If not, the line is still
+        // kept in simple_metadata
+        let entry = MetadataEntry::parse(value, span);
+        if entry.is_none() {
+            diagnostics.push(ctx.warning(
+                DiagnosticKind::BadMetadataLine,
+                value,
+                format!("metadata line without `::` separator `{value}`"),
+            ));
+        }
 
-            // Much trimming!
-            let value = line_trimmed.trim_start_matches(META).trim().into();
-            doc.simple_metadata.push(value);
-        // Page number (not sure why this would happen)
-        } else if line_trimmed.starts_with(PAGE) {
-            // Try to capture volume and page numbers
-            if let Some(cap) = PAGE_PATTERN.captures(line_trimmed) {
-                let vol = cap[1].into();
-                let page = cap[2].into();
-
-                doc.content
-                    .push(Content::PageNumber(PageNumber { vol, page }));
-            } else {
-                // An exception is raised here in the Python library; I haven't done anything
-            }
-        // Riwāya
-        } else if line_trimmed.starts_with(RWY) {
-            // First add the whole line
-            doc.content.push(Content::Paragraph {
+        events.push(Event::Metadata {
+            text: value.into(),
+            entry,
+        });
+    // Page number (not sure why this would happen)
+    } else if line_trimmed.starts_with(PAGE) {
+        // Try to capture volume and page numbers
+        if let Some((vol, page)) = find_page_number(line_trimmed) {
+            let vol = ctx.text(vol);
+            let page = ctx.text(page);
+
+            content.push(Content::PageNumber(PageNumber { vol, page, span }));
+        } else {
+            // An exception is raised here in the Python library; we just make a note of it
+            // and keep the line as is
+            diagnostics.push(ctx.page_warning(line_trimmed));
+
+            content.push(Content::Unknown {
                 orig: line_trimmed.into(),
-                para_type: ParaType::Riwayat,
+                span,
             });
+        }
+    // Riwāya
+    } else if line_trimmed.starts_with(RWY) {
+        // First add the whole line
+        content.push(Content::Paragraph {
+            orig: line_trimmed.into(),
+            para_type: ParaType::Riwayat,
+            span,
+        });
+
+        // Then parse everything after the riwāya tag
+        let double_trimmed = line_trimmed.trim_start_matches(RWY);
+        let first_line = parse_line(
+            double_trimmed,
+            None,
+            true,
+            ctx,
+            &mut state.entity,
+            &mut diagnostics,
+        );
 
-            // Then parse everything after the riwāya tag
-            let double_trimmed = line_trimmed.trim_start_matches(RWY);
-            let first_line = parse_line(double_trimmed, None, true);
+        if let Some(first_line_content) = first_line {
+            content.push(Content::Line(first_line_content));
+        }
+    // Route from
+    } else if line_trimmed.starts_with(ROUTE_FROM) {
+        let kind = LineType::RouteOrDistance;
+        let parsed_line = parse_line(
+            line_trimmed,
+            Some(kind),
+            false,
+            ctx,
+            &mut state.entity,
+            &mut diagnostics,
+        );
 
-            if let Some(first_line_content) = first_line {
-                doc.content.push(Content::Line(first_line_content));
-            }
-        // Route from
-        } else if line_trimmed.starts_with(ROUTE_FROM) {
-            let kind = LineType::RouteOrDistance;
-            let parsed_line = parse_line(line_trimmed, Some(kind), false);
+        if let Some(parsed_line_content) = parsed_line {
+            content.push(Content::Line(parsed_line_content));
+        }
+    // Morphological pattern
+    } else if let Some(cap) = morpho_pattern.captures(line_trimmed) {
+        let category = ctx.text(&cap[1]);
+
+        content.push(Content::MorphologicalPattern {
+            orig: line_trimmed.into(),
+            category,
+            span,
+        });
+    // Region. This has to come before paragraphs, since its tags start with `#`, too
+    } else if region_pattern.is_match(line_trimmed) {
+        content.push(Content::AdministrativeRegion {
+            orig: line_trimmed.into(),
+            span,
+        });
+    // Paragraph
+    } else if para_pattern.is_match(line_trimmed) {
+        // This line will be parsed without the initial paragraph marker
+        let no_marker = &line_trimmed[1..];
+
+        // If line contains hemistich marker (which can occur in the middle)...
+        if line_trimmed.contains(HEMI) {
+            let kind = LineType::Verse;
+            let verse_parsed = parse_line(
+                no_marker,
+                Some(kind),
+                false,
+                ctx,
+                &mut state.entity,
+                &mut diagnostics,
+            );
 
-            if let Some(parsed_line_content) = parsed_line {
-                doc.content.push(Content::Line(parsed_line_content));
+            if let Some(verse_content) = verse_parsed {
+                content.push(Content::Line(verse_content));
             }
-        // Morphological pattern
-        } else if let Some(cap) = morpho_pattern.captures(line_trimmed) {
-            let category = cap[1].into();
-
-            doc.content.push(Content::MorphologicalPattern {
+        } else {
+            content.push(Content::Paragraph {
                 orig: line_trimmed.into(),
-                category,
+                para_type: ParaType::Normal,
+                span,
             });
-        // Paragraph
-        } else if para_pattern.is_match(line_trimmed) {
-            // This line will be parsed without the initial paragraph marker
-            let no_marker = &line_trimmed[1..];
-
-            // If line contains hemistich marker (which can occur in the middle)...
-            if line_trimmed.contains(HEMI) {
-                let kind = LineType::Verse;
-                let verse_parsed = parse_line(no_marker, Some(kind), false);
-
-                if let Some(verse_content) = verse_parsed {
-                    doc.content.push(Content::Line(verse_content));
-                }
-            } else {
-                doc.content.push(Content::Paragraph {
-                    orig: line_trimmed.into(),
-                    para_type: ParaType::Normal,
-                });
-
-                let first_line = parse_line(no_marker, None, false);
-                if let Some(first_line_content) = first_line {
-                    doc.content.push(Content::Line(first_line_content));
-                }
-            }
-        // Line
-        } else if line_trimmed.starts_with(LINE) {
-            let parsed_line = parse_line(line_trimmed, None, false);
 
-            if let Some(parsed_line_content) = parsed_line {
-                doc.content.push(Content::Line(parsed_line_content));
-            }
-        // Editorial (whatever that means)
-        } else if line_trimmed.starts_with(EDITORIAL) {
-            doc.content.push(Content::Editorial);
-        // Heading
-        } else if line_trimmed.starts_with(HEADER1) {
-            // I think "value" means the actual heading content, minus the tag
-            let mut value = line_trimmed.to_owned();
-
-            for tag in HEADERS {
-                value = value.replace(tag, "");
+            let first_line = parse_line(
+                no_marker,
+                None,
+                false,
+                ctx,
+                &mut state.entity,
+                &mut diagnostics,
+            );
+            if let Some(first_line_content) = first_line {
+                content.push(Content::Line(first_line_content));
             }
+        }
+    // Line
+    } else if line_trimmed.starts_with(LINE) {
+        let parsed_line = parse_line(
+            line_trimmed,
+            None,
+            false,
+            ctx,
+            &mut state.entity,
+            &mut diagnostics,
+        );
 
-            value = remove_phrase_lv_tags(value);
+        if let Some(parsed_line_content) = parsed_line {
+            content.push(Content::Line(parsed_line_content));
+        }
+    // Editorial (whatever that means)
+    } else if line_trimmed.starts_with(EDITORIAL) {
+        content.push(Content::Editorial {
+            orig: line_trimmed.into(),
+            span,
+        });
+    // Heading
+    } else if line_trimmed.starts_with(HEADER1) {
+        // The following comment is copied from the Python library
+        // TODO: capture tags as PhraseParts
+        // (For now, the whole line is kept in orig, so that they aren't lost)
+        let value = header_value(line_trimmed);
+
+        // Now we determine the heading level
+        let level = header_level(line_trimmed);
+
+        content.push(Content::SectionHeader {
+            orig: line_trimmed.into(),
+            value: ctx.text(as_suffix(line_trimmed, &value)),
+            level,
+            span,
+        });
+    // Dictionary content (?)
+    } else if line_trimmed.starts_with(DIC) {
+        // Strip tags
+        let mut no_tag = line_trimmed.to_owned();
+        for tag in DICTIONARIES {
+            no_tag = no_tag.replace(tag, "");
+        }
 
-            // The following comment is copied from the Python library
-            // TODO: capture tags as PhraseParts
+        // Parse stripped line
+        let first_line = parse_line(
+            as_suffix(line_trimmed, &no_tag),
+            None,
+            false,
+            ctx,
+            &mut state.entity,
+            &mut diagnostics,
+        );
 
-            // Now we determine the heading level
-            let mut level: u32 = 1;
+        // Determine dictionary content type
+        let dic_type = dic_type(line_trimmed);
 
-            if line_trimmed.contains(HEADER5) {
-                level = 5;
-            } else if line_trimmed.contains(HEADER4) {
-                level = 4;
-            } else if line_trimmed.contains(HEADER3) {
-                level = 3;
-            } else if line_trimmed.contains(HEADER2) {
-                level = 2;
-            }
+        // Add dictionary unit
+        content.push(Content::DictionaryUnit {
+            orig: line_trimmed.into(),
+            dic_type,
+            span,
+        });
 
-            doc.content.push(Content::SectionHeader { value, level });
-        // Dictionary content (?)
-        } else if line_trimmed.starts_with(DIC) {
-            // Strip tags
-            let mut no_tag = line_trimmed.to_owned();
-            for tag in DICTIONARIES {
-                no_tag = no_tag.replace(tag, "");
-            }
+        // If there was other line content, add that
+        if let Some(first_line_content) = first_line {
+            content.push(Content::Line(first_line_content));
+        }
+    // Doxographical content (?)
+    } else if line_trimmed.starts_with(DOX) {
+        // Strip tags
+        let mut no_tag = line_trimmed.to_owned();
+        for tag in DOXOGRAPHICAL {
+            no_tag = no_tag.replace(tag, "");
+        }
 
-            // Parse stripped line
-            let first_line = parse_line(&no_tag, None, false);
+        // Parse stripped line
+        let first_line = parse_line(
+            as_suffix(line_trimmed, &no_tag),
+            None,
+            false,
+            ctx,
+            &mut state.entity,
+            &mut diagnostics,
+        );
 
-            // Determine dictionary content type
-            let dic_type = if line_trimmed.contains(DIC_LEX) {
-                DicType::Lex
-            } else if line_trimmed.contains(DIC_NIS) {
-                DicType::Nis
-            } else if line_trimmed.contains(DIC_TOP) {
-                DicType::Top
-            } else {
-                DicType::Bib
-            };
+        // Determine doxographical content type
+        let dox_type = dox_type(line_trimmed);
 
-            // Add dictionary unit
-            doc.content.push(Content::DictionaryUnit {
-                orig: line_trimmed.into(),
-                dic_type,
-            });
+        // Add doxographical item
+        content.push(Content::DoxographicalItem {
+            orig: line_trimmed.into(),
+            dox_type,
+            span,
+        });
 
-            // If there was other line content, add that
-            if let Some(first_line_content) = first_line {
-                doc.content.push(Content::Line(first_line_content));
-            }
-        // Doxographical content (?)
-        } else if line_trimmed.starts_with(DOX) {
-            // Strip tags
-            let mut no_tag = line_trimmed.to_owned();
-            for tag in DOXOGRAPHICAL {
-                no_tag = no_tag.replace(tag, "");
-            }
+        // If there was other line content, add that
+        if let Some(first_line_content) = first_line {
+            content.push(Content::Line(first_line_content));
+        }
+    // Biographical item
+    } else if bio_pattern.is_match(line_trimmed)
+        || line_trimmed.starts_with(BIO)
+        || line_trimmed.starts_with(EVENT)
+    {
+        // Strip tags
+        let mut no_tag = line_trimmed.to_owned();
+        for tag in BIOS_EVENTS {
+            no_tag = no_tag.replace(tag, "");
+        }
 
-            // Parse stripped line
-            let first_line = parse_line(&no_tag, None, false);
+        // Parse stripped line
+        let first_line = parse_line(
+            as_suffix(line_trimmed, &no_tag),
+            None,
+            false,
+            ctx,
+            &mut state.entity,
+            &mut diagnostics,
+        );
 
-            // Determine doxographical content type
-            let dox_type = if line_trimmed.contains(DOX_SEC) {
-                DoxType::Sec
-            } else {
-                DoxType::Pos
+        // Determine type of biographical item
+        let be_type = be_type(line_trimmed);
+
+        // Add biographical item
+        content.push(Content::BioOrEvent {
+            orig: line_trimmed.into(),
+            be_type,
+            span,
+        });
+
+        // If there was other line content, add that
+        if let Some(first_line_content) = first_line {
+            content.push(Content::Line(first_line_content));
+        }
+    } else if !line_trimmed.is_empty() {
+        // Nothing we know how to parse. Blank lines aren't worth mentioning
+        diagnostics.push(ctx.warning(
+            DiagnosticKind::UnrecognizedLine,
+            line_trimmed,
+            "line does not match any known construct".into(),
+        ));
+
+        content.push(Content::Unknown {
+            orig: line_trimmed.into(),
+            span,
+        });
+    }
+
+    events.extend(content.into_iter().map(Event::Content));
+    events.extend(diagnostics.into_iter().map(Event::Diagnostic));
+
+    Ok(())
+}
+
+// In strict mode, the diagnostics that are errors. The column is counted from the start of
+// the line, in characters
+fn strict_error(input: &str, diagnostic: &Diagnostic) -> Option<ParseError> {
+    let span = diagnostic.span;
+    let line_start = input[..span.start].rfind('\n').map_or(0, |i| i + 1);
+    let line = span.line;
+    let column = input[line_start..span.start].chars().count() + 1;
+    let text = input[span.range()].to_owned();
+
+    match diagnostic.kind {
+        DiagnosticKind::MalformedPageTag => Some(ParseError::MalformedPageTag {
+            line,
+            column,
+            tag: text,
+        }),
+        DiagnosticKind::MalformedEntityTag => Some(ParseError::MalformedEntityTag {
+            line,
+            column,
+            tag: text,
+        }),
+        DiagnosticKind::BadMetadataLine => Some(ParseError::BadMetadataLine { line, column, text }),
+        _ => None,
+    }
+}
+
+/// # Errors
+///
+/// Same as [`parser`], and in strict mode (see [`ParseOptions`]), the first malformed tag or
+/// metadata line.
+pub fn parser_with_options(input: &str, options: ParseOptions) -> Result<Document<'_>, ParseError> {
+    // This is our return value, gods willing
+    let mut doc = Document::default();
+
+    let mut state = ParserState::default();
+
+    if options.parallel {
+        state = parse_lines_parallel(input, &mut doc)?;
+    } else {
+        // Main loop
+        for (i, line) in input.lines().enumerate() {
+            // Keep track of where we are, in case something goes wrong
+            let ctx = LineCtx {
+                number: i + 1,
+                start: offset_in(input, line).unwrap_or(0),
+                raw: line,
             };
 
-            // Add doxographical item
-            doc.content.push(Content::DoxographicalItem {
-                orig: line_trimmed.into(),
-                dox_type,
-            });
+            let mut events = Vec::new();
+            parse_source_line(ctx, &mut state, &mut events)?;
 
-            // If there was other line content, add that
-            if let Some(first_line_content) = first_line {
-                doc.content.push(Content::Line(first_line_content));
+            for event in events {
+                doc.push_event(event);
             }
-        // Biographical item
-        } else if bio_pattern.is_match(line_trimmed)
-            || line_trimmed.starts_with(BIO)
-            || line_trimmed.starts_with(EVENT)
+        }
+    }
+
+    if options.strict {
+        if let Some(err) = doc
+            .diagnostics
+            .iter()
+            .find_map(|diagnostic| strict_error(input, diagnostic))
         {
-            // Strip tags
-            let mut no_tag = line_trimmed.to_owned();
-            for tag in BIOS_EVENTS {
-                no_tag = no_tag.replace(tag, "");
-            }
+            return Err(err);
+        }
+    }
 
-            // Parse stripped line
-            let first_line = parse_line(&no_tag, None, false);
-
-            // Determine type of biographical item
-            let be_type =
-                if line_trimmed.contains(LIST_NAMES_FULL) || line_trimmed.contains(LIST_NAMES) {
-                    BeType::Names
-                } else if line_trimmed.contains(BIO_REF_FULL) || line_trimmed.contains(BIO_REF) {
-                    BeType::Ref
-                } else if line_trimmed.contains(BIO_WOM_FULL) || line_trimmed.contains(BIO_WOM) {
-                    BeType::Wom
-                } else if line_trimmed.contains(LIST_EVENTS) {
-                    BeType::Events
-                } else if line_trimmed.contains(EVENT) {
-                    BeType::Event
-                } else {
-                    BeType::Man
-                };
+    if options.lossless {
+        let lines: Vec<String> = input.split_inclusive('\n').map(Into::into).collect();
+        let line_count = lines.len();
 
-            // Add biographical item
-            doc.content.push(Content::BioOrEvent {
-                orig: line_trimmed.into(),
-                be_type,
-            });
+        doc.trivia = Some(Trivia {
+            lines,
+            canonical: Vec::new(),
+            header_end: state.header_end,
+        });
 
-            // If there was other line content, add that
-            if let Some(first_line_content) = first_line {
-                doc.content.push(Content::Line(first_line_content));
-            }
-        // Region
-        } else if region_pattern.is_match(line_trimmed) {
-            doc.content.push(Content::AdministrativeRegion {
-                orig: line_trimmed.into(),
-            });
-        } else {
-            // Can just no-op this (which I'm sure the compiler does anyway)
-            // continue;
+        let canonical = canonical_lines(&doc, line_count);
+        if let Some(trivia) = &mut doc.trivia {
+            trivia.canonical = canonical;
         }
     }
 
@@ -785,29 +1077,37 @@ pub fn parser(input: &str) -> Result<Document> {
 mod tests {
     use super::*;
     use once_cell::sync::Lazy;
+    use proptest::prelude::*;
     use std::fs;
 
-    static PARSED: Lazy<Document> = Lazy::new(|| {
+    static PARSED: Lazy<Document<'static>> = Lazy::new(|| {
         let full_text = fs::read_to_string("test.md").unwrap();
-        parser(&full_text).unwrap()
+        parser(&full_text).unwrap().into_owned()
     });
 
     #[test]
+    #[allow(clippy::too_many_lines)]
     fn bio_event() {
         let content = &PARSED.content;
 
         // Man, full tag
-        if let Content::BioOrEvent { orig: _, be_type } = &content[2] {
+        if let Content::BioOrEvent {
+            orig: _,
+            be_type,
+            span: _,
+        } = &content[2]
+        {
             assert!(be_type.is_man());
 
             if let Content::Line(Line {
                 text_only: _,
                 parts,
                 line_type: _,
+                span: _,
             }) = &content[3]
             {
                 assert_eq!(
-                    parts[0].as_text_part().unwrap(),
+                    parts[0].as_text_part().unwrap().0,
                     "أبو عمرو ابن العلاء واسمه"
                 );
             } else {
@@ -818,17 +1118,23 @@ mod tests {
         }
 
         // Man, shorthand
-        if let Content::BioOrEvent { orig: _, be_type } = &content[8] {
+        if let Content::BioOrEvent {
+            orig: _,
+            be_type,
+            span: _,
+        } = &content[8]
+        {
             assert!(be_type.is_man());
 
             if let Content::Line(Line {
                 text_only: _,
                 parts,
                 line_type: _,
+                span: _,
             }) = &content[9]
             {
                 assert_eq!(
-                    parts[0].as_text_part().unwrap(),
+                    parts[0].as_text_part().unwrap().0,
                     "أبو عمرو ابن العلاء واسمه"
                 );
             } else {
@@ -839,17 +1145,23 @@ mod tests {
         }
 
         // Woman, full tag
-        if let Content::BioOrEvent { orig: _, be_type } = &content[14] {
+        if let Content::BioOrEvent {
+            orig: _,
+            be_type,
+            span: _,
+        } = &content[14]
+        {
             assert!(be_type.is_wom());
 
             if let Content::Line(Line {
                 text_only: _,
                 parts,
                 line_type: _,
+                span: _,
             }) = &content[15]
             {
                 assert_eq!(
-                    parts[0].as_text_part().unwrap(),
+                    parts[0].as_text_part().unwrap().0,
                     "1729 - صمعة بنت أحمد بن محمد بن عبيد الله الرئيس النيسابورية من ولد عثمان بن"
                 );
             } else {
@@ -860,17 +1172,23 @@ mod tests {
         }
 
         // Woman, shorthand
-        if let Content::BioOrEvent { orig: _, be_type } = &content[17] {
+        if let Content::BioOrEvent {
+            orig: _,
+            be_type,
+            span: _,
+        } = &content[17]
+        {
             assert!(be_type.is_wom());
 
             if let Content::Line(Line {
                 text_only: _,
                 parts,
                 line_type: _,
+                span: _,
             }) = &content[18]
             {
                 assert_eq!(
-                    parts[0].as_text_part().unwrap(),
+                    parts[0].as_text_part().unwrap().0,
                     "1729 - صمعة بنت أحمد بن محمد بن عبيد الله الرئيس النيسابورية من ولد عثمان بن"
                 );
             } else {
@@ -881,17 +1199,23 @@ mod tests {
         }
 
         // Reference, full tag
-        if let Content::BioOrEvent { orig: _, be_type } = &content[20] {
+        if let Content::BioOrEvent {
+            orig: _,
+            be_type,
+            span: _,
+        } = &content[20]
+        {
             assert!(be_type.is_ref());
 
             if let Content::Line(Line {
                 text_only: _,
                 parts,
                 line_type: _,
+                span: _,
             }) = &content[21]
             {
                 assert_eq!(
-                    parts[0].as_text_part().unwrap(),
+                    parts[0].as_text_part().unwrap().0,
                     "[a cross-reference, for both men and women]"
                 );
             } else {
@@ -902,17 +1226,23 @@ mod tests {
         }
 
         // Reference, shorthand
-        if let Content::BioOrEvent { orig: _, be_type } = &content[23] {
+        if let Content::BioOrEvent {
+            orig: _,
+            be_type,
+            span: _,
+        } = &content[23]
+        {
             assert!(be_type.is_ref());
 
             if let Content::Line(Line {
                 text_only: _,
                 parts,
                 line_type: _,
+                span: _,
             }) = &content[24]
             {
                 assert_eq!(
-                    parts[0].as_text_part().unwrap(),
+                    parts[0].as_text_part().unwrap().0,
                     "[a cross-reference, for both men and women]"
                 );
             } else {
@@ -923,17 +1253,23 @@ mod tests {
         }
 
         // Names, full tag
-        if let Content::BioOrEvent { orig: _, be_type } = &content[26] {
+        if let Content::BioOrEvent {
+            orig: _,
+            be_type,
+            span: _,
+        } = &content[26]
+        {
             assert!(be_type.is_names());
 
             if let Content::Line(Line {
                 text_only: _,
                 parts,
                 line_type: _,
+                span: _,
             }) = &content[27]
             {
                 assert_eq!(
-                    parts[0].as_text_part().unwrap(),
+                    parts[0].as_text_part().unwrap().0,
                     "-وفيها ولد: (@)(@@) المحدث عفيف"
                 );
             } else {
@@ -944,17 +1280,23 @@ mod tests {
         }
 
         // Names, shorthand
-        if let Content::BioOrEvent { orig: _, be_type } = &content[29] {
+        if let Content::BioOrEvent {
+            orig: _,
+            be_type,
+            span: _,
+        } = &content[29]
+        {
             assert!(be_type.is_names());
 
             if let Content::Line(Line {
                 text_only: _,
                 parts,
                 line_type: _,
+                span: _,
             }) = &content[30]
             {
                 assert_eq!(
-                    parts[0].as_text_part().unwrap(),
+                    parts[0].as_text_part().unwrap().0,
                     "-وفيها ولد: (@)(@@) المحدث عفيف"
                 );
             } else {
@@ -965,21 +1307,36 @@ mod tests {
         }
 
         // Events
-        if let Content::BioOrEvent { orig: _, be_type } = &content[32] {
+        if let Content::BioOrEvent {
+            orig: _,
+            be_type,
+            span: _,
+        } = &content[32]
+        {
             assert!(be_type.is_events());
         } else {
             panic!("Not a BioOrEvent");
         }
 
         // Event
-        if let Content::BioOrEvent { orig: _, be_type } = &content[34] {
+        if let Content::BioOrEvent {
+            orig: _,
+            be_type,
+            span: _,
+        } = &content[34]
+        {
             assert!(be_type.is_event());
         } else {
             panic!("Not a BioOrEvent");
         }
 
         // Another man
-        if let Content::BioOrEvent { orig: _, be_type } = &content[49] {
+        if let Content::BioOrEvent {
+            orig: _,
+            be_type,
+            span: _,
+        } = &content[50]
+        {
             assert!(be_type.is_man());
         } else {
             panic!("Not a BioOrEvent");
@@ -990,25 +1347,45 @@ mod tests {
     fn dictionary_units() {
         let content = &PARSED.content;
 
-        if let Content::DictionaryUnit { orig: _, dic_type } = &content[36] {
+        if let Content::DictionaryUnit {
+            orig: _,
+            dic_type,
+            span: _,
+        } = &content[36]
+        {
             assert!(dic_type.is_nis());
         } else {
             panic!("Not a DictionaryUnit");
         }
 
-        if let Content::DictionaryUnit { orig: _, dic_type } = &content[38] {
+        if let Content::DictionaryUnit {
+            orig: _,
+            dic_type,
+            span: _,
+        } = &content[38]
+        {
             assert!(dic_type.is_top());
         } else {
             panic!("Not a DictionaryUnit");
         }
 
-        if let Content::DictionaryUnit { orig: _, dic_type } = &content[40] {
+        if let Content::DictionaryUnit {
+            orig: _,
+            dic_type,
+            span: _,
+        } = &content[40]
+        {
             assert!(dic_type.is_lex());
         } else {
             panic!("Not a DictionaryUnit");
         }
 
-        if let Content::DictionaryUnit { orig: _, dic_type } = &content[42] {
+        if let Content::DictionaryUnit {
+            orig: _,
+            dic_type,
+            span: _,
+        } = &content[42]
+        {
             assert!(dic_type.is_bib());
         } else {
             panic!("Not a DictionaryUnit");
@@ -1020,9 +1397,10 @@ mod tests {
         let content = &PARSED.content;
 
         // Level 5 heading (orig, text, level)
+        let (_, value, level, _) = content[63].as_section_header().unwrap();
         assert_eq!(
-            content[62].as_section_header().unwrap(),
-            (&"(نهج ابن هشام في هذا الكتاب) :".to_string(), &5u32)
+            (value, level),
+            (&Cow::from("(نهج ابن هشام في هذا الكتاب) :"), &5u32)
         );
     }
 
@@ -1031,11 +1409,13 @@ mod tests {
         let content = &PARSED.content;
 
         // Level 1 heading (orig, text, level)
+        let (_, value, level, _) = content[59].as_section_header().unwrap();
         assert_eq!(
-            content[58].as_section_header().unwrap(),
+            (value, level),
             (
-                &"ذكر سرد النسب الزكي من محمد صلى الله عليه وآله وسلم، إلى آدم عليه السلام"
-                    .to_string(),
+                &Cow::from(
+                    "ذكر سرد النسب الزكي من محمد صلى الله عليه وآله وسلم، إلى آدم عليه السلام"
+                ),
                 &1u32
             )
         );
@@ -1049,18 +1429,19 @@ mod tests {
             text_only: _,
             parts,
             line_type,
-        }) = &content[55]
+            span: _,
+        }) = &content[56]
         {
             assert!(line_type.is_normal());
             assert!(parts[0].is_isnad());
 
             assert_eq!(
-                parts[1].as_text_part().unwrap(),
+                parts[1].as_text_part().unwrap().0,
                 "this section contains isnād"
             );
 
             assert!(parts[2].is_matn());
-            assert_eq!(parts[3].as_text_part().unwrap(), "this section");
+            assert_eq!(parts[3].as_text_part().unwrap().0, "this section");
         } else {
             panic!("Not a Line");
         }
@@ -1077,6 +1458,18 @@ mod tests {
             simple_metadata[simple_metadata.len() - 1],
             "999.MiscINFO	:: NODATA"
         );
+
+        let metadata = &text_parsed.metadata;
+
+        assert_eq!(metadata.entries.len(), 33);
+        assert_eq!(metadata.book_uri(), Some("#0213.IbnHisham.SiraNabawiyya"));
+        assert_eq!(metadata.author_died(), Some(213));
+        assert_eq!(metadata.book_vols(), Some(2));
+        assert_eq!(metadata.get("AuthorAKA"), None);
+        assert_eq!(
+            metadata.editor(),
+            Some("مصطفى السقا وإبراهيم الأبياري وعبد الحفيظ الشلبي")
+        );
     }
 
     #[test]
@@ -1087,7 +1480,8 @@ mod tests {
             text_only: _,
             parts,
             line_type,
-        }) = &content[71]
+            span: _,
+        }) = &content[72]
         {
             assert!(line_type.is_normal());
 
@@ -1097,6 +1491,7 @@ mod tests {
                 prefix,
                 extent,
                 ne_type,
+                span: _,
             } = &parts[1]
             {
                 assert_eq!(*prefix, 0);
@@ -1106,14 +1501,14 @@ mod tests {
                 panic!("Not a NamedEntity");
             }
 
-            if let LinePart::NamedEntityText { text, ne_type } = &parts[2] {
+            if let LinePart::NamedEntityText { text, ne_type, .. } = &parts[2] {
                 assert_eq!(text, r#"نزيل: 1"018:"#);
                 assert!(ne_type.is_soc());
             } else {
                 panic!("Not NamedEntityText");
             }
 
-            assert_eq!(parts[3].as_text_part().unwrap(), "واسط.. شيخ: معمر");
+            assert_eq!(parts[3].as_text_part().unwrap().0, "واسط.. شيخ: معمر");
         } else {
             panic!("Not a Line");
         }
@@ -1127,17 +1522,19 @@ mod tests {
             text_only: _,
             parts,
             line_type,
-        }) = &content[81]
+            span: _,
+        }) = &content[82]
         {
             assert!(line_type.is_normal());
 
+            let (resp, t_type, category, review, _) = parts[1].as_open_tag_auto().unwrap();
             assert_eq!(
-                parts[1].as_open_tag_auto().unwrap(),
+                (resp, t_type, category, review),
                 (
-                    &"RES".to_string(),
-                    &"TYPE".to_string(),
-                    &"Category".to_string(),
-                    &"fr".to_string()
+                    &Cow::from("RES"),
+                    &Cow::from("TYPE"),
+                    &Cow::from("Category"),
+                    &Cow::from("fr")
                 )
             );
         } else {
@@ -1153,17 +1550,19 @@ mod tests {
             text_only: _,
             parts,
             line_type,
-        }) = &content[79]
+            span: _,
+        }) = &content[80]
         {
             assert!(line_type.is_normal());
 
+            let (user, t_type, t_subtype, t_subsubtype, _) = parts[1].as_open_tag_user().unwrap();
             assert_eq!(
-                parts[1].as_open_tag_user().unwrap(),
+                (user, t_type, t_subtype, t_subsubtype),
                 (
-                    &"USER".to_string(),
-                    &"CAT".to_string(),
-                    &"SUBCAT".to_string(),
-                    &"SUBSUBCAT".to_string()
+                    &Cow::from("USER"),
+                    &Cow::from("CAT"),
+                    &Cow::from("SUBCAT"),
+                    &Cow::from("SUBSUBCAT")
                 )
             );
         } else {
@@ -1179,19 +1578,20 @@ mod tests {
             text_only: _,
             parts,
             line_type,
-        }) = &content[63]
+            span: _,
+        }) = &content[64]
         {
             assert!(line_type.is_verse());
 
             assert_eq!(
-                parts[0].as_text_part().unwrap(),
+                parts[0].as_text_part().unwrap().0,
                 "وجمع العرب تحت لواء الرسول محمد عليه الصلاة"
             );
 
-            assert_eq!(parts[1].as_hemistich().unwrap(), "%~%");
+            assert_eq!(parts[1].as_hemistich().unwrap().0, "%~%");
 
             assert_eq!(
-                parts[2].as_text_part().unwrap(),
+                parts[2].as_text_part().unwrap().0,
                 "والسلام، وما يضاف إلى ذلك من"
             );
         } else {
@@ -1203,7 +1603,12 @@ mod tests {
     fn riwayat() {
         let content = &PARSED.content;
 
-        if let Content::Paragraph { orig: _, para_type } = &content[54] {
+        if let Content::Paragraph {
+            orig: _,
+            para_type,
+            span: _,
+        } = &content[55]
+        {
             assert!(para_type.is_riwayat());
         } else {
             panic!("Not a Paragraph");
@@ -1218,13 +1623,14 @@ mod tests {
             text_only: _,
             parts,
             line_type,
-        }) = &content[57]
+            span: _,
+        }) = &content[58]
         {
             assert!(line_type.is_route_or_distance());
             assert!(parts[0].is_route_from());
             assert!(parts[2].is_route_towa());
 
-            assert_eq!(parts[5].as_text_part().unwrap(), "distance_as_recorded");
+            assert_eq!(parts[5].as_text_part().unwrap().0, "distance_as_recorded");
         } else {
             panic!("Not a Line");
         }
@@ -1238,11 +1644,12 @@ mod tests {
             text_only: _,
             parts,
             line_type,
-        }) = &content[96]
+            span: _,
+        }) = &content[97]
         {
             assert!(line_type.is_normal());
 
-            if let LinePart::PageNumber(PageNumber { vol, page }) = &parts[0] {
+            if let LinePart::PageNumber(PageNumber { vol, page, .. }) = &parts[0] {
                 assert_eq!(vol, "01M");
                 assert_eq!(page, "001");
             } else {
@@ -1259,10 +1666,498 @@ mod tests {
             text_only: _,
             parts,
             line_type,
-        }) = &content[67]
+            span: _,
+        }) = &content[68]
         {
             assert!(line_type.is_normal());
             assert!(parts[1].is_milestone());
         }
     }
+
+    #[test]
+    fn bad_magic() {
+        let err = parser("# Not a mARkdown file").unwrap_err();
+        assert_eq!(err, ParseError::BadMagic { line: 1, column: 1 });
+    }
+
+    #[test]
+    fn bad_metadata_line() {
+        let input = "######OpenITI#\n#META# 000.SortField Shamela_0023833\n#META#Header#End#";
+        let doc = parser(input).unwrap();
+
+        // Kept, but not as an entry
+        assert_eq!(doc.simple_metadata, ["000.SortField Shamela_0023833"]);
+        assert!(doc.metadata.entries.is_empty());
+
+        assert_eq!(doc.diagnostics.len(), 1);
+        assert!(doc.diagnostics[0].kind.is_bad_metadata_line());
+        assert_eq!(
+            &input[doc.diagnostics[0].span.range()],
+            "000.SortField Shamela_0023833"
+        );
+    }
+
+    #[test]
+    fn malformed_entity_tag() {
+        let input = "######OpenITI#\n\n~~ الصلاة @P9 والسلام\n~~@TOPx والسلام";
+        let doc = parser(input).unwrap();
+
+        let kinds: Vec<(DiagnosticKind, &str)> = doc
+            .diagnostics
+            .iter()
+            .map(|d| (d.kind, &input[d.span.range()]))
+            .collect();
+        assert_eq!(
+            kinds,
+            [
+                (DiagnosticKind::MalformedEntityTag, "@P9"),
+                (DiagnosticKind::MalformedEntityTag, "@TOPx"),
+            ]
+        );
+
+        // The text is still there
+        let texts: Vec<&str> = doc
+            .content
+            .iter()
+            .filter_map(Content::as_line)
+            .flat_map(|line| &line.parts)
+            .filter_map(|part| part.as_text_part())
+            .map(|(text, _)| text.as_ref())
+            .collect();
+        assert_eq!(texts, ["الصلاة", "@P9", "والسلام", "@TOPx والسلام"]);
+        assert!(doc.content.iter().all(|item| !item.is_unknown()));
+    }
+
+    #[test]
+    fn diagnostics() {
+        let input = "######OpenITI#\n#META#Header#End#\nPageVxx\n\n~~ text PageV01\nstray\n#META# 999.MiscINFO\t:: NODATA";
+        let doc = parser(input).unwrap();
+
+        let kinds: Vec<(DiagnosticKind, usize)> = doc
+            .diagnostics
+            .iter()
+            .map(|d| (d.kind, d.span.line))
+            .collect();
+        assert_eq!(
+            kinds,
+            [
+                (DiagnosticKind::MalformedPageTag, 3),
+                (DiagnosticKind::MalformedPageTag, 5),
+                (DiagnosticKind::UnrecognizedLine, 6),
+                (DiagnosticKind::MetadataAfterHeader, 7),
+            ]
+        );
+
+        assert_eq!(&input[doc.diagnostics[1].span.range()], "text PageV01");
+        assert_eq!(doc.simple_metadata.len(), 1);
+        assert!(PARSED
+            .diagnostics
+            .iter()
+            .all(|d| d.kind.is_unrecognized_line()));
+    }
+
+    #[test]
+    fn strict() {
+        let strict = ParseOptions {
+            strict: true,
+            ..ParseOptions::default()
+        };
+        let error = |input| parser_with_options(input, strict).unwrap_err();
+
+        assert_eq!(
+            error("######OpenITI#\n\n~~ الصلاة @P9 والسلام\n~~@TOPx والسلام"),
+            ParseError::MalformedEntityTag {
+                line: 3,
+                column: 11,
+                tag: "@P9".into()
+            }
+        );
+        assert_eq!(
+            error("######OpenITI#\n#META# 000.SortField Shamela_0023833\n#META#Header#End#"),
+            ParseError::BadMetadataLine {
+                line: 2,
+                column: 8,
+                text: "000.SortField Shamela_0023833".into()
+            }
+        );
+
+        let input = "######OpenITI#\n#META#Header#End#\nstray\n~~ text PageV01\n";
+        assert_eq!(
+            error(input),
+            ParseError::MalformedPageTag {
+                line: 4,
+                column: 4,
+                tag: "text PageV01".into()
+            }
+        );
+        let parallel = ParseOptions {
+            parallel: true,
+            ..strict
+        };
+        assert_eq!(parser_with_options(input, parallel), Err(error(input)));
+
+        // Other diagnostics don't count
+        let input = "######OpenITI#\n#META#Header#End#\nstray\n#META# 999.MiscINFO\t:: NODATA";
+        assert!(parser_with_options(input, strict).is_ok());
+    }
+
+    #[test]
+    fn tag_only_lines() {
+        let input = "######OpenITI#\n#META#Header#End#\n# @MATN@\n~~ Milestone300\n~~\n### |EDITOR| ينظر الأصل\n";
+        let doc = parser(input).unwrap();
+
+        // Nothing is lost, even without text
+        let parts: Vec<Vec<&LinePart>> = doc
+            .content
+            .iter()
+            .filter_map(Content::as_line)
+            .map(|line| line.parts.iter().collect())
+            .collect();
+        assert_eq!(parts.len(), 3);
+        assert!(parts[0][0].is_matn());
+        assert!(parts[1][0].is_milestone());
+        assert!(parts[2].is_empty());
+        assert_eq!(&input[doc.content[3].span().range()], "~~");
+
+        let (orig, _) = doc.content[4].as_editorial().unwrap();
+        assert_eq!(orig, "### |EDITOR| ينظر الأصل");
+    }
+
+    #[test]
+    fn open_tag_optional_groups() {
+        let doc = parser("######OpenITI#\n~~ @USER@CAT_SUB@ and @RES@TYPE@Cat@ text").unwrap();
+        let parts = &doc.content[0].as_line().unwrap().parts;
+
+        assert_eq!(parts[0].as_open_tag_user().unwrap().3, "");
+        assert_eq!(parts[2].as_open_tag_auto().unwrap().3, "");
+    }
+
+    #[test]
+    fn no_panics() {
+        let nasty = [
+            "",
+            "######OpenITI#\n~~@P",
+            "######OpenITI#\n~~@SRC",
+            "######OpenITI#\n~~@SOC1",
+            "######OpenITI#\n~~@S@T@P",
+            "######OpenITI#\n# @PER0",
+            "######OpenITI#\n### $BIO_MAN$ @TOP",
+            "######OpenITI#\n### $DIC_NIS$ PageV",
+            "######OpenITI#\n~~ PageV01P",
+            "######OpenITI#\n#META#",
+            "######OpenITI#\n#",
+            "######OpenITI#\n~~%~%@MATN@@HUKM@#$#FROM",
+        ];
+
+        for input in nasty {
+            let _ = parser(input);
+        }
+    }
+
+    // Lines put together from bits of markup (and anything else), so that generated input
+    // gets past the magic value and into the parser proper
+    pub fn markup_line() -> impl Strategy<Value = String> {
+        let bits = vec![
+            "#",
+            "~~",
+            "### |",
+            "### $",
+            "### |EDITOR|",
+            "#META#",
+            "#META#Header#End#",
+            "::",
+            "PageV",
+            "P",
+            "@P",
+            "@TOP",
+            "@SOC0",
+            "@YD",
+            "ms",
+            "Milestone300",
+            "%~%",
+            "@MATN@",
+            "@",
+            "_",
+            " ",
+            "وبغداد",
+            "َ",
+        ];
+        let piece = prop_oneof![
+            prop::sample::select(bits).prop_map(String::from),
+            "[0-9]{1,3}",
+            any::<String>(),
+        ];
+
+        prop::collection::vec(piece, 0..8).prop_map(|pieces| pieces.concat())
+    }
+
+    proptest! {
+        #[test]
+        fn no_panics_generated(lines in prop::collection::vec(markup_line(), 0..8)) {
+            let input = format!("######OpenITI#\n{}", lines.join("\n"));
+
+            // Past the magic value, there's nothing that can make parsing fail
+            let doc = parser(&input).unwrap();
+
+            // And every span is a valid slice of the input
+            for item in &doc.content {
+                prop_assert!(input.get(item.span().range()).is_some());
+
+                for part in item.as_line().map_or(&[][..], |line| &line.parts) {
+                    prop_assert!(input.get(part.span().range()).is_some());
+                }
+            }
+            for diagnostic in &doc.diagnostics {
+                prop_assert!(input.get(diagnostic.span.range()).is_some());
+            }
+        }
+    }
+
+    #[test]
+    fn entity_prefix() {
+        let input = "######OpenITI#\n#META# 000.SortField :: JK_011476\n#META#Header#End#\n# دخل @TOP11 وبغداد ثم @PER22 وَبِمحمد بن علي و@SOC01 الحنفية ثم @TOP31 في\n";
+        let doc = parser(input).unwrap();
+
+        let line = doc.content.iter().find_map(Content::as_line).unwrap();
+        let entities: Vec<(&str, &str)> = line
+            .parts
+            .iter()
+            .filter_map(|part| part.as_named_entity_text())
+            .map(|(prefix, text, ..)| (prefix.as_ref(), text.as_ref()))
+            .collect();
+
+        // Vowels on the prefix go with it; a prefix that would swallow the whole first word is
+        // left alone, with a warning
+        assert_eq!(
+            entities,
+            [
+                ("و", "بغداد"),
+                ("وَبِ", "محمد بن"),
+                ("", "الحنفية"),
+                ("", "في")
+            ]
+        );
+        assert_eq!(doc.diagnostics.len(), 1);
+        assert!(doc.diagnostics[0].kind.is_malformed_entity_prefix());
+        assert_eq!(&input[doc.diagnostics[0].span.range()], "في");
+
+        // Written back the way it was
+        assert_eq!(write(&doc), input);
+    }
+
+    #[test]
+    fn entity_across_lines() {
+        let input = "######OpenITI#\n# حدثنا @PER05 محمد بن\n~~إسحاق بن PageV01P002 يسار قال\n# ثم @TOP12 وفي\n~~مكة\n# قال @PER13\n~~فمحمد بن علي\n# عن @PER03 زيد\n# بن ثابت\n";
+        let doc = parser(input).unwrap();
+
+        let pieces: Vec<(&str, &str)> = doc
+            .content
+            .iter()
+            .filter_map(Content::as_line)
+            .flat_map(|line| &line.parts)
+            .filter_map(LinePart::as_named_entity_text)
+            .map(|(prefix, text, ..)| (prefix.as_ref(), text.as_ref()))
+            .collect();
+
+        // Pieces of one entity, on either side of a line break or a page marker. The prefix
+        // is taken from whichever line the first word is on. The last entity is cut off by
+        // the paragraph break
+        assert_eq!(
+            pieces,
+            [
+                ("", "محمد بن"),
+                ("", "إسحاق بن"),
+                ("", "يسار"),
+                ("و", "في"),
+                ("", "مكة"),
+                ("ف", "محمد بن علي"),
+                ("", "زيد"),
+            ]
+        );
+
+        let last = doc.content.iter().rev().find_map(Content::as_line).unwrap();
+        assert!(last.parts[0].is_text_part());
+
+        // Same thing, in parallel
+        let parallel = ParseOptions {
+            parallel: true,
+            ..ParseOptions::default()
+        };
+        assert_eq!(parser_with_options(input, parallel).unwrap(), doc);
+    }
+
+    #[test]
+    fn spans() {
+        let input = "######OpenITI#\n\n### $BIO_MAN$ ابن @P11 والعلاء واسمه\n~~زبان PageV01P002";
+        let doc = parser(input).unwrap();
+
+        let bio = doc.content[0].span();
+        assert_eq!(bio.line, 3);
+        assert_eq!(&input[bio.range()], "### $BIO_MAN$ ابن @P11 والعلاء واسمه");
+
+        let line = doc.content[1].as_line().unwrap();
+        assert_eq!(&input[line.span.range()], " ابن @P11 والعلاء واسمه");
+
+        let texts: Vec<&str> = line
+            .parts
+            .iter()
+            .map(|p| &input[p.span().range()])
+            .collect();
+        assert_eq!(texts, ["ابن", "@P11", "العلاء", "واسمه"]);
+
+        // The prefix sits just before the entity's span
+        let entity = line.parts[2].as_named_entity_text().unwrap();
+        assert_eq!(&input[entity.3.range()], "العلاء");
+        assert_eq!(&input[entity.3.start - entity.0.len()..entity.3.start], "و");
+        assert_eq!(line.parts[3].span().line, 3);
+
+        let page = doc.content[2].as_line().unwrap().parts[1].span();
+        assert_eq!(page.line, 4);
+        assert_eq!(&input[page.range()], "PageV01P002");
+
+        // Text that had to be put together isn't in the source, so it gets the whole line
+        let input = "######OpenITI#\nعن ### $BIO_MAN$ 𐭘 فلان";
+        let doc = parser(input).unwrap();
+        let line = doc.content[1].as_line().unwrap();
+        assert_eq!(line.parts[0].as_text_part().unwrap().0, "عن  𐭘 فلان");
+        assert_eq!(&input[line.span.range()], "عن ### $BIO_MAN$ 𐭘 فلان");
+
+        // The same word more than once in a line: each piece gets its own
+        let input = "######OpenITI#\n~~زيد @PER01 زيد زيد\n### | زيد Milestone300 زيد";
+        let doc = parser(input).unwrap();
+        let line = doc.content[0].as_line().unwrap();
+        let starts: Vec<(usize, &str)> = line
+            .parts
+            .iter()
+            .map(|p| (p.span().start, &input[p.span().range()]))
+            .collect();
+        assert_eq!(
+            starts,
+            [(17, "زيد"), (24, "@PER01"), (31, "زيد"), (38, "زيد")]
+        );
+
+        // And the text borrowed from the input is the piece in its place, too
+        let (text, span) = line.parts[3].as_text_part().unwrap();
+        assert_eq!(offset_in(input, text), Some(span.start));
+
+        let header = doc.content[1].span();
+        assert_eq!(&input[header.range()], "### | زيد Milestone300 زيد");
+    }
+
+    #[test]
+    fn spans_whole_file() {
+        let full_text = fs::read_to_string("test.md").unwrap();
+        let line_ends: Vec<usize> = full_text
+            .match_indices('\n')
+            .map(|(i, _)| i)
+            .chain([full_text.len()])
+            .collect();
+
+        let check = |span: Span| {
+            let line_start = if span.line > 1 {
+                line_ends[span.line - 2] + 1
+            } else {
+                0
+            };
+
+            assert!(span.start >= line_start && span.end <= line_ends[span.line - 1]);
+        };
+
+        for item in &PARSED.content {
+            check(item.span());
+
+            if let Content::Line(line) = item {
+                for part in &line.parts {
+                    check(part.span());
+
+                    if let LinePart::TextPart { text, span } = part {
+                        assert_eq!(text, &full_text[span.range()]);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn borrowed_strings() {
+        let input =
+            "######OpenITI#\n# plain text PageV01P002 more\n~~@PER01 زيد went home\n### | title";
+        let doc = parser(input).unwrap();
+
+        let borrowed = |text: &Cow<str>| matches!(text, Cow::Borrowed(_));
+
+        // Whatever appears verbatim in the input points into it
+        let (orig, _, _) = doc.content[0].as_paragraph().unwrap();
+        assert!(borrowed(orig));
+
+        let line = doc.content[1].as_line().unwrap();
+        let page_number = line.parts[1].as_page_number().unwrap();
+        assert!(borrowed(&page_number.vol) && borrowed(&page_number.page));
+        assert!(line.parts.iter().all(|part| match part {
+            LinePart::TextPart { text, .. } => borrowed(text),
+            _ => true,
+        }));
+
+        // But text with tags taken out of the middle has to be put together
+        assert_eq!(line.text_only.as_deref(), Some("plain text more"));
+        assert!(!borrowed(line.text_only.as_ref().unwrap()));
+
+        let entity = doc.content[2].as_line().unwrap();
+        assert!(borrowed(entity.parts[1].as_named_entity_text().unwrap().0));
+
+        let (_, value, _, _) = doc.content[3].as_section_header().unwrap();
+        assert!(borrowed(value));
+
+        // Owned copies compare equal, and outlive the input
+        let owned = {
+            let copy = input.to_owned();
+            parser(&copy).unwrap().into_owned()
+        };
+        assert_eq!(owned, doc);
+    }
+
+    #[test]
+    fn unknown_lines() {
+        let content = &PARSED.content;
+        assert_eq!(content[49].as_unknown().unwrap().0, "[السيرة النبوية]");
+
+        let doc = parser("######OpenITI#\nPageVxx\n\n~~ text PageV01\nstray").unwrap();
+        let orig: Vec<&str> = doc
+            .content
+            .iter()
+            .filter_map(|c| c.as_unknown().map(|(orig, _)| orig.as_ref()))
+            .collect();
+
+        assert_eq!(orig, ["PageVxx", "stray"]);
+        assert_eq!(
+            doc.content[1].as_line().unwrap().parts[0]
+                .as_text_part()
+                .unwrap()
+                .0,
+            "text PageV01"
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_round_trip() {
+        let json = serde_json::to_string(&*PARSED).unwrap();
+        let doc: Document = serde_json::from_str(&json).unwrap();
+
+        assert_eq!(doc.content.len(), PARSED.content.len());
+        assert_eq!(serde_json::to_string(&doc).unwrap(), json);
+
+        let value = serde_json::to_value(&PARSED.content[63]).unwrap();
+        assert_eq!(value["type"], "section_header");
+        assert_eq!(value["level"], 5);
+
+        let value = serde_json::to_value(&PARSED.content[2]).unwrap();
+        assert_eq!(value["type"], "bio_or_event");
+        assert_eq!(value["be_type"], "man");
+
+        let value = serde_json::to_value(&PARSED.content[97]).unwrap();
+        assert_eq!(value["type"], "line");
+        assert_eq!(value["parts"][0]["type"], "page_number");
+        assert_eq!(value["parts"][0]["vol"], "01M");
+    }
 }
@@ -1,17 +1,129 @@
-use anyhow::Result;
-use oimdp_rs::parser;
-use std::{env, fs};
+use anyhow::{bail, Result};
+use oimdp_rs::{
+    import_conll, insert_milestones, parser, ConllOptions, ConllSplit, EntityLabels, TagScheme,
+    MILESTONE_TOKENS,
+};
+use std::{env, fs, io};
+
+// `milestones [--every N] input [output]`: write the file back with milestones inserted
+// (into itself, unless told otherwise)
+fn milestones(args: &[String]) -> Result<()> {
+    let mut every = MILESTONE_TOKENS;
+    let mut paths = Vec::new();
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if arg == "--every" {
+            let Some(value) = args.next() else {
+                bail!("--every needs a number of tokens");
+            };
+            every = value.parse()?;
+        } else {
+            paths.push(arg);
+        }
+    }
+
+    let (input, output) = match paths[..] {
+        [input] => (input, input),
+        [input, output] => (input, output),
+        _ => bail!("usage: milestones [--every N] input [output]"),
+    };
+
+    let full_text = fs::read_to_string(input)?;
+    fs::write(output, insert_milestones(&full_text, every))?;
+
+    Ok(())
+}
+
+// `conll [--bioes] [--milestones] input [output]`: write out named entities as training
+// data (to stdout, unless told otherwise)
+fn conll(args: &[String]) -> Result<()> {
+    let mut options = ConllOptions::default();
+    let mut paths = Vec::new();
+
+    for arg in args {
+        match arg.as_str() {
+            "--bioes" => options.scheme = TagScheme::Bioes,
+            "--milestones" => options.split = ConllSplit::Milestone,
+            _ => paths.push(arg),
+        }
+    }
+
+    let (input, output) = match paths[..] {
+        [input] => (input, None),
+        [input, output] => (input, Some(output)),
+        _ => bail!("usage: conll [--bioes] [--milestones] input [output]"),
+    };
+
+    let full_text = fs::read_to_string(input)?;
+    let doc = parser(&full_text)?;
+
+    match output {
+        Some(output) => doc.write_conll(&options, io::BufWriter::new(fs::File::create(output)?))?,
+        None => doc.write_conll(&options, io::stdout().lock())?,
+    }
+
+    Ok(())
+}
+
+// `import [--jsonl] input predictions [output]`: tag the entities a model found, and write
+// the file back (into itself, unless told otherwise). Conflicts go to stderr
+fn import(args: &[String]) -> Result<()> {
+    let jsonl = args.iter().any(|arg| arg == "--jsonl");
+    let paths: Vec<&String> = args.iter().filter(|arg| *arg != "--jsonl").collect();
+
+    let (input, predictions, output) = match paths[..] {
+        [input, predictions] => (input, predictions, input),
+        [input, predictions, output] => (input, predictions, output),
+        _ => bail!("usage: import [--jsonl] input predictions [output]"),
+    };
+
+    let full_text = fs::read_to_string(input)?;
+    let predictions = fs::read_to_string(predictions)?;
+    let labels = EntityLabels::default();
+
+    let import = if jsonl {
+        #[cfg(feature = "serde")]
+        {
+            oimdp_rs::import_spans(&full_text, &predictions, &labels)?
+        }
+        #[cfg(not(feature = "serde"))]
+        bail!("JSONL predictions need the serde feature")
+    } else {
+        import_conll(&full_text, &predictions, &labels)?
+    };
+
+    for conflict in &import.conflicts {
+        eprintln!("{}: {:?} `{}`", conflict.line, conflict.kind, conflict.text);
+    }
+
+    fs::write(output, &import.output)?;
+    println!(
+        "Added {} tags; {} conflicts",
+        import.inserted,
+        import.conflicts.len()
+    );
+
+    Ok(())
+}
 
 fn main() -> Result<()> {
     let args: Vec<String> = env::args().collect();
 
+    match args.get(1).map(String::as_str) {
+        Some("milestones") => return milestones(&args[2..]),
+        Some("conll") => return conll(&args[2..]),
+        Some("import") => return import(&args[2..]),
+        _ => {}
+    }
+
     let mut file_path = "test.md";
     if args.len() > 1 {
         file_path = &args[1];
     }
 
     let full_text = fs::read_to_string(file_path)?;
-    let text_parsed = parser(&full_text).unwrap();
+    let text_parsed = parser(&full_text)?;
 
     println!("Parsed {} content items", text_parsed.content.len());
 
@@ -0,0 +1,168 @@
+use crate::structures::Span;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+
+// Placeholders used in the header when a field has no real value
+const NO_VALUE: [&str; 2] = ["NODATA", "NOTGIVEN"];
+
+// Metadata
+// The #META# header, parsed into key-value entries. The order of the source is kept
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Metadata<'a> {
+    pub entries: Vec<MetadataEntry<'a>>,
+}
+
+// A line like "000.SortField :: Shamela_0023833" has prefix "000", field "SortField",
+// and value "Shamela_0023833". Some lines (e.g., "999 :: ...") have no field name
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct MetadataEntry<'a> {
+    pub prefix: Cow<'a, str>,
+    pub field: Cow<'a, str>,
+    pub value: Option<Cow<'a, str>>,
+    pub span: Span,
+}
+
+impl<'a> MetadataEntry<'a> {
+    // Expects the line without the #META# tag; returns None if there's no "::" separator
+    pub(crate) fn parse(text: &'a str, span: Span) -> Option<Self> {
+        let (key, value) = text.split_once("::")?;
+        let key = key.trim();
+
+        let (prefix, field) = key.split_once('.').unwrap_or((key, ""));
+
+        let value = value.trim();
+        let value = if value.is_empty() || NO_VALUE.contains(&value) {
+            None
+        } else {
+            Some(value.into())
+        };
+
+        Some(Self {
+            prefix: prefix.into(),
+            field: field.into(),
+            value,
+            span,
+        })
+    }
+
+    #[must_use]
+    pub fn into_owned(self) -> MetadataEntry<'static> {
+        MetadataEntry {
+            prefix: Cow::Owned(self.prefix.into_owned()),
+            field: Cow::Owned(self.field.into_owned()),
+            value: self.value.map(|value| Cow::Owned(value.into_owned())),
+            span: self.span,
+        }
+    }
+}
+
+impl Metadata<'_> {
+    #[must_use]
+    pub fn into_owned(self) -> Metadata<'static> {
+        Metadata {
+            entries: self
+                .entries
+                .into_iter()
+                .map(MetadataEntry::into_owned)
+                .collect(),
+        }
+    }
+
+    /// The first value recorded for a field, by name (e.g., "BookURI"). Fields are often
+    /// repeated under different prefixes, with only some of them filled in
+    #[must_use]
+    pub fn get(&self, field: &str) -> Option<&str> {
+        self.entries
+            .iter()
+            .filter(|entry| entry.field == field)
+            .find_map(|entry| entry.value.as_deref())
+    }
+
+    #[must_use]
+    pub fn book_uri(&self) -> Option<&str> {
+        self.get("BookURI")
+    }
+
+    #[must_use]
+    pub fn author_name(&self) -> Option<&str> {
+        self.get("AuthorNAME")
+    }
+
+    /// Death date (AH) of the author
+    #[must_use]
+    pub fn author_died(&self) -> Option<u32> {
+        self.numeric("AuthorDIED")
+    }
+
+    #[must_use]
+    pub fn book_title(&self) -> Option<&str> {
+        self.get("BookTITLE")
+    }
+
+    #[must_use]
+    pub fn editor(&self) -> Option<&str> {
+        self.get("EdEDITOR")
+    }
+
+    #[must_use]
+    pub fn book_vols(&self) -> Option<u32> {
+        self.numeric("BookVOLS")
+    }
+
+    // Same as get, but skips over values that aren't plain numbers
+    fn numeric(&self, field: &str) -> Option<u32> {
+        self.entries
+            .iter()
+            .filter(|entry| entry.field == field)
+            .find_map(|entry| entry.value.as_deref()?.parse().ok())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entry() {
+        let entry =
+            MetadataEntry::parse("021.BookSUBJ\t:: حديث :: تراجم", Span::default()).unwrap();
+
+        assert_eq!(entry.prefix, "021");
+        assert_eq!(entry.field, "BookSUBJ");
+        assert_eq!(entry.value.as_deref(), Some("حديث :: تراجم"));
+
+        let entry =
+            MetadataEntry::parse("999\t:: THIS IS A MADE UP TEST", Span::default()).unwrap();
+        assert_eq!((&*entry.prefix, &*entry.field), ("999", ""));
+
+        let entry = MetadataEntry::parse("011.AuthorBORN\t:: NOTGIVEN", Span::default()).unwrap();
+        assert_eq!(entry.value, None);
+
+        assert!(MetadataEntry::parse("011.AuthorBORN", Span::default()).is_none());
+    }
+
+    #[test]
+    fn accessors() {
+        let metadata = Metadata {
+            entries: [
+                "011.AuthorDIED\t:: NODATA",
+                "019.AuthorDIED\t:: 213",
+                "022.BookVOLS\t:: NODATA",
+                "020.BookTITLE\t:: السيرة النبوية لابن هشام",
+            ]
+            .iter()
+            .filter_map(|line| MetadataEntry::parse(line, Span::default()))
+            .collect(),
+        };
+
+        assert_eq!(metadata.author_died(), Some(213));
+        assert_eq!(metadata.book_vols(), None);
+        assert_eq!(metadata.book_title(), Some("السيرة النبوية لابن هشام"));
+        assert_eq!(metadata.book_uri(), None);
+    }
+}
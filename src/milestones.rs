@@ -0,0 +1,488 @@
+use crate::lexer::{Lexer, Tag};
+use crate::tags::{HEADERS, LINE, META_END, MILESTONE_NUMBERED, PAGE};
+use crate::{
+    entity_digits, offset_in, pages::PageIndex, parse_source_line, push_text,
+    sections::SectionTree, stream::Event, structures::*, tokens::arabic_tokens, LineCtx,
+    ParserState,
+};
+use std::ops::Range;
+
+// Milestones
+// OpenITI texts are cut into units by milestone markers, each of which closes the unit
+// before it: the older Milestone300 (every 300 tokens, unnumbered), or the newer numbered
+// ms0001. Text reuse data and citations are keyed on these units, so here we split a
+// Document into them
+
+#[derive(Clone, Debug)]
+pub struct MilestoneChunk<'a> {
+    // From the marker if it has one; otherwise counted from 1. Text after the last marker
+    // gets the next number
+    pub number: u32,
+    // Plain text, with lines and pieces of lines joined by spaces
+    pub text: String,
+    pub tokens: usize,
+    // The pages (see PageIndex) that the first and last tokens are on. None if no page
+    // marker follows
+    pub first_page: Option<&'a PageNumber<'a>>,
+    pub last_page: Option<&'a PageNumber<'a>>,
+    // Path of the section the chunk's first token is in (see Section::path); empty before
+    // the first header
+    pub section_path: Vec<usize>,
+    // Indices of the content items the chunk takes text from. A line with a milestone in
+    // the middle is shared by the chunks on either side
+    pub range: Range<usize>,
+}
+
+impl MilestoneChunk<'_> {
+    const fn new(number: u32, start: usize) -> Self {
+        Self {
+            number,
+            text: String::new(),
+            tokens: 0,
+            first_page: None,
+            last_page: None,
+            section_path: Vec::new(),
+            range: start..start,
+        }
+    }
+
+    fn push_text(&mut self, text: &str) {
+        push_text(&mut self.text, text);
+        self.tokens += arabic_tokens(text).count();
+    }
+}
+
+struct Splitter<'a> {
+    chunks: Vec<MilestoneChunk<'a>>,
+    current: MilestoneChunk<'a>,
+    // Where each chunk's first token is, for finding its section
+    first_items: Vec<usize>,
+    first_item: Option<usize>,
+    // Byte offsets of the pieces of text with each chunk's first and last tokens, for
+    // finding their pages
+    offsets: Vec<Option<(usize, usize)>>,
+    current_offsets: Option<(usize, usize)>,
+}
+
+impl Splitter<'_> {
+    fn text(&mut self, text: &str, index: usize, offset: usize) {
+        let tokens = self.current.tokens;
+        self.current.push_text(text);
+
+        if self.current.tokens > tokens {
+            self.first_item.get_or_insert(index);
+
+            let first = self.current_offsets.map_or(offset, |(first, _)| first);
+            self.current_offsets = Some((first, offset));
+        }
+    }
+
+    // Headers can have milestones in them, too, which only orig has kept
+    fn header(&mut self, orig: &str, value: &str, index: usize, span: Span) {
+        if orig.is_empty() {
+            self.text(value, index, span.start);
+            return;
+        }
+
+        let mut line = orig.to_owned();
+        for tag in HEADERS {
+            line = line.replace(tag, "");
+        }
+
+        for token in Lexer::new(&line) {
+            match token.tag {
+                None => self.text(token.src, index, span.start),
+                Some(Tag::Milestone(number)) => self.milestone(number, index),
+                Some(_) => {}
+            }
+        }
+    }
+
+    fn milestone(&mut self, number: Option<&str>, index: usize) {
+        let number = number
+            .and_then(|number| number.parse().ok())
+            .unwrap_or(self.current.number);
+
+        let next = MilestoneChunk::new(number + 1, index);
+        let mut done = std::mem::replace(&mut self.current, next);
+        done.number = number;
+        done.range.end = index + 1;
+
+        self.first_items
+            .push(self.first_item.take().unwrap_or(index));
+        self.offsets.push(self.current_offsets.take());
+        self.chunks.push(done);
+    }
+}
+
+impl<'a> Document<'a> {
+    /// Split the document into milestone units, in order
+    #[must_use]
+    pub fn milestones(&'a self) -> Vec<MilestoneChunk<'a>> {
+        let mut splitter = Splitter {
+            chunks: Vec::new(),
+            current: MilestoneChunk::new(1, 0),
+            first_items: Vec::new(),
+            first_item: None,
+            offsets: Vec::new(),
+            current_offsets: None,
+        };
+
+        for (i, item) in self.content.iter().enumerate() {
+            match item {
+                Content::Line(line) => {
+                    for part in &line.parts {
+                        match part {
+                            LinePart::TextPart { text, span } => {
+                                splitter.text(text, i, span.start);
+                            }
+                            LinePart::NamedEntityText {
+                                prefix, text, span, ..
+                            } => {
+                                splitter.text(&format!("{prefix}{text}"), i, span.start);
+                            }
+                            LinePart::Milestone { number, .. } => {
+                                splitter.milestone(number.as_deref(), i);
+                            }
+                            _ => {}
+                        }
+                    }
+                }
+                Content::SectionHeader {
+                    orig, value, span, ..
+                } => splitter.header(orig, value, i, *span),
+                _ => {}
+            }
+
+            splitter.current.range.end = i + 1;
+        }
+
+        if let Some(first_item) = splitter.first_item {
+            let last = splitter.current;
+            splitter.chunks.push(last);
+            splitter.first_items.push(first_item);
+            splitter.offsets.push(splitter.current_offsets);
+        }
+
+        let tree = SectionTree::new(self);
+        let pages = PageIndex::new(self);
+        let page_at = |offset| pages.page_at(offset).map(|page| page.number);
+
+        for ((chunk, &first_item), offsets) in splitter
+            .chunks
+            .iter_mut()
+            .zip(&splitter.first_items)
+            .zip(&splitter.offsets)
+        {
+            if let Some(section) = tree.find(first_item).last() {
+                chunk.section_path.clone_from(&section.path);
+            }
+
+            if let Some((first, last)) = *offsets {
+                chunk.first_page = page_at(first);
+                chunk.last_page = page_at(last);
+            }
+        }
+
+        splitter.chunks
+    }
+}
+
+// Milestone insertion
+// Working on the source text rather than on a Document, so that everything else in it is
+// left exactly as it was. Markers already in the text stay where they are, since citations
+// may depend on them, but they're all renumbered (as ms0001, etc.) in order of appearance
+
+/// How many tokens OpenITI puts between milestones
+pub const MILESTONE_TOKENS: usize = 300;
+
+fn milestone_marker(number: usize) -> String {
+    format!("{MILESTONE_NUMBERED}{number:04}")
+}
+
+struct Inserter {
+    every: usize,
+    // Tokens since the last marker
+    count: usize,
+    number: usize,
+    // Words still to come of a named entity, which we don't want to cut in two
+    entity_words: usize,
+}
+
+impl Inserter {
+    fn marker(&mut self) -> String {
+        self.count = 0;
+        self.number += 1;
+        milestone_marker(self.number)
+    }
+
+    // Words are counted the way the parser counts the words of a named entity, but only
+    // their Arabic tokens go toward the next milestone. Returns whether the text ended with
+    // a new marker
+    fn text(&mut self, text: &str, out: &mut String) -> bool {
+        let mut copied = 0;
+
+        for (start, word) in text
+            .split_whitespace()
+            .map(|word| (offset_in(text, word).unwrap_or_default(), word))
+        {
+            let tokens = arabic_tokens(word).count();
+            self.count += tokens;
+            self.entity_words = self.entity_words.saturating_sub(1);
+
+            if self.every > 0 && self.count >= self.every && self.entity_words == 0 && tokens > 0 {
+                let end = start + word.len();
+                out.push_str(&text[copied..end]);
+                out.push(' ');
+                out.push_str(&self.marker());
+                copied = end;
+            }
+        }
+
+        out.push_str(&text[copied..]);
+        copied == text.len()
+    }
+
+    fn line(&mut self, line: &str, out: &mut String) {
+        let mut tokens = Lexer::new(line).peekable();
+        while let Some(token) = tokens.next() {
+            match token.tag {
+                None => {
+                    // A tag right after the word keeps its distance from the marker
+                    if self.text(token.src, out) && tokens.peek().is_some() {
+                        out.push(' ');
+                    }
+                }
+                Some(Tag::Milestone(_)) => {
+                    let marker = self.marker();
+                    out.push_str(&marker);
+                }
+                Some(Tag::Entity(_, digits)) => {
+                    self.entity_words =
+                        entity_digits(digits).map_or(0, |(_, extent)| extent as usize);
+                    out.push_str(token.src);
+                }
+                Some(_) => out.push_str(token.src),
+            }
+        }
+    }
+}
+
+// Whether Document::milestones counts the tokens of a line: those of text lines, and
+// section headers, but not of editorial notes, morphological patterns, etc. The parser
+// decides which is which
+fn counts_tokens(number: usize, line: &str, state: &mut ParserState) -> bool {
+    let ctx = LineCtx {
+        number,
+        start: 0,
+        raw: line,
+    };
+    let mut events = Vec::new();
+
+    parse_source_line(ctx, state, &mut events).is_ok()
+        && events.iter().any(|event| {
+            matches!(
+                event,
+                Event::Content(Content::Line(_) | Content::SectionHeader { .. })
+            )
+        })
+}
+
+/// Insert a milestone marker after every `every` Arabic tokens (see [`arabic_tokens`])
+///
+/// Tokens are counted from the last marker, whether it was already there or not, on the same
+/// lines as in [`Document::milestones`]: text and section headers. Markers go between words, after any punctuation, and never inside a tag or the words of a named
+/// entity. Existing markers are kept where they are and renumbered. With `every` set to 0,
+/// markers are only renumbered. Everything up to the end of the metadata header is left
+/// alone
+#[must_use]
+pub fn insert_milestones(input: &str, every: usize) -> String {
+    let mut out = String::with_capacity(input.len() + input.len() / 100);
+    let mut inserter = Inserter {
+        every,
+        count: 0,
+        number: 0,
+        entity_words: 0,
+    };
+
+    // Everything up to the end of the header is left alone, whatever it looks like
+    let header_end = input
+        .lines()
+        .position(|line| line.trim() == META_END)
+        .unwrap_or_default();
+
+    let mut state = ParserState::default();
+
+    for (i, full_line) in input.split_inclusive('\n').enumerate() {
+        let line = full_line.trim_end_matches(['\n', '\r']);
+        let ending = &full_line[line.len()..];
+
+        // Same as in the parser, a named entity only runs on into a `~~` line (or past a page
+        // marker on its own line)
+        let trimmed = line.trim_start();
+        if !(trimmed.starts_with(LINE) || trimmed.starts_with(PAGE)) {
+            inserter.entity_words = 0;
+        }
+
+        if i <= header_end || !counts_tokens(i + 1, line, &mut state) {
+            out.push_str(full_line);
+            continue;
+        }
+
+        inserter.line(line, &mut out);
+        out.push_str(ending);
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser;
+
+    #[test]
+    fn chunks() {
+        let input = "######OpenITI#\n#META#Header#End#\n### | باب\n# قال ابن هشام Milestone300 حدثنا\n~~زياد PageV01P001 قال ms0007 ثم\n### || فصل\n# آخر";
+        let doc = parser(input).unwrap();
+        let chunks = doc.milestones();
+
+        let summary: Vec<(u32, &str, usize)> = chunks
+            .iter()
+            .map(|chunk| (chunk.number, chunk.text.as_str(), chunk.tokens))
+            .collect();
+
+        assert_eq!(
+            summary,
+            [
+                (1, "باب قال ابن هشام", 4),
+                (7, "حدثنا زياد قال", 3),
+                (8, "ثم فصل آخر", 3),
+            ]
+        );
+
+        // Page markers close pages: the first chunk ends on page 1, the second runs on past
+        // it, and there are no markers after that
+        let page = |chunk: &MilestoneChunk| chunk.last_page.map(|p| p.page.to_string());
+        assert_eq!(page(&chunks[0]), Some("001".into()));
+        assert_eq!(chunks[1].first_page.unwrap().page, "001");
+        assert_eq!(page(&chunks[1]), None);
+        assert_eq!(page(&chunks[2]), None);
+
+        assert_eq!(chunks[0].section_path, [1]);
+        assert_eq!(chunks[1].section_path, [1]);
+        assert_eq!(chunks[2].section_path, [1]);
+        assert_eq!(chunks[2].range, 3..7);
+    }
+
+    #[test]
+    fn insertion() {
+        let input = "######OpenITI#\r\n#META# 000.Key :: قال ابن هشام\r\n#META#Header#End#\r\n### | باب الأول\r\n# قال ابن هشام، حدثنا @PER02 زياد بن عبد الله عن Milestone300 محمد\r\n~~PageV01P001 ثم قال: الخبر الآخر";
+        let output = insert_milestones(input, 3);
+
+        assert_eq!(
+            output,
+            "######OpenITI#\r\n#META# 000.Key :: قال ابن هشام\r\n#META#Header#End#\r\n### | باب الأول\r\n# قال ms0001 ابن هشام، حدثنا ms0002 @PER02 زياد بن عبد ms0003 الله عن ms0004 محمد\r\n~~PageV01P001 ثم قال: ms0005 الخبر الآخر"
+        );
+
+        // A marker doesn't run into a tag right after the word
+        assert_eq!(
+            insert_milestones("######OpenITI#\n# اب@PER01 ج\n", 1),
+            "######OpenITI#\n# اب ms0001 @PER01 ج ms0002\n"
+        );
+
+        // Nothing above the end of the header is text, even if it looks like it
+        assert_eq!(
+            insert_milestones("######OpenITI#\n# قال\n#META#Header#End#\n# قال\n", 1),
+            "######OpenITI#\n# قال\n#META#Header#End#\n# قال ms0001\n"
+        );
+
+        // Only renumbering
+        assert_eq!(
+            insert_milestones("######OpenITI#\n# a Milestone300 b ms0009 c\n", 0),
+            "######OpenITI#\n# a ms0001 b ms0002 c\n"
+        );
+
+        // Marker by marker, the chunks come out the right size, unless a named entity or a
+        // marker that was already there got in the way
+        let full_text = std::fs::read_to_string("test.md").unwrap();
+        let without = full_text.replace(" Milestone300", "");
+        let output = insert_milestones(&without, MILESTONE_TOKENS);
+
+        let doc = parser(&output).unwrap();
+        let chunks = doc.milestones();
+        assert!(chunks.len() > 800);
+
+        let exact = chunks
+            .iter()
+            .filter(|chunk| chunk.tokens == MILESTONE_TOKENS)
+            .count();
+        assert!(exact * 10 > chunks.len() * 9);
+        assert!(chunks
+            .iter()
+            .all(|chunk| chunk.tokens <= MILESTONE_TOKENS + 10));
+
+        // Nothing but the markers changed
+        let markers = regex::Regex::new(" ms[0-9]{4}").unwrap();
+        assert_eq!(markers.replace_all(&output, ""), without);
+    }
+
+    #[test]
+    fn insertion_other_lines() {
+        let input = "######OpenITI#\n#META#Header#End#\n# قال ابن هشام حدثنا\n### |EDITOR| ينظر الأصل هنا\n#~:صيغة: فعل يفعل\n#$#PROV بغداد #$#TYPE مدينة #$#STTL الكوفة\n~~زياد بن عبد الله عن محمد\n";
+        let output = insert_milestones(input, 3);
+
+        // Editorial notes, morphological patterns, and regions don't count, and don't get
+        // markers
+        assert_eq!(
+            output,
+            "######OpenITI#\n#META#Header#End#\n# قال ابن هشام ms0001 حدثنا\n### |EDITOR| ينظر الأصل هنا\n#~:صيغة: فعل يفعل\n#$#PROV بغداد #$#TYPE مدينة #$#STTL الكوفة\n~~زياد بن ms0002 عبد الله عن ms0003 محمد\n"
+        );
+
+        // Which is how the chunks come out, too
+        let doc = parser(&output).unwrap();
+        assert!(doc.content.iter().any(Content::is_administrative_region));
+        let tokens: Vec<(u32, usize)> = doc
+            .milestones()
+            .iter()
+            .map(|chunk| (chunk.number, chunk.tokens))
+            .collect();
+        assert_eq!(tokens, [(1, 3), (2, 3), (3, 3), (4, 1)]);
+    }
+
+    #[test]
+    fn whole_file() {
+        let full_text = std::fs::read_to_string("test.md").unwrap();
+        let doc = parser(&full_text).unwrap();
+        let chunks = doc.milestones();
+
+        // One chunk per Milestone300, plus whatever is left at the end
+        let markers = full_text.matches("Milestone300").count();
+        assert!(
+            chunks.len() == markers || chunks.len() == markers + 1,
+            "{} {markers}",
+            chunks.len()
+        );
+
+        for (n, chunk) in chunks.iter().enumerate() {
+            assert_eq!(chunk.number as usize, n + 1);
+        }
+
+        // Milestones are inserted every 300 tokens, give or take
+        let typical = chunks[1..chunks.len() - 1]
+            .iter()
+            .filter(|chunk| (280..=320).contains(&chunk.tokens))
+            .count();
+        assert!(
+            typical * 10 > chunks.len() * 9,
+            "{typical} of {}",
+            chunks.len()
+        );
+
+        // Only the first starts before the first header
+        assert!(chunks[0].section_path.is_empty());
+        assert!(chunks[1..]
+            .iter()
+            .all(|chunk| !chunk.section_path.is_empty()));
+    }
+}
@@ -0,0 +1,197 @@
+use crate::{push_text, structures::*};
+use std::ops::Range;
+
+// Pages
+// Page markers (PageV01P005) come at the end of the page they number, either on a line of
+// their own or in the middle of one. A page is everything between the previous marker and
+// its own. Text after the last marker isn't on any page we know of, so it's left out
+
+#[derive(Clone, Debug)]
+pub struct Page<'a> {
+    pub number: &'a PageNumber<'a>,
+    // Indices of the content items with something on the page. A line with a page marker in
+    // the middle is shared by the pages on either side
+    pub range: Range<usize>,
+    pub content: &'a [Content<'a>],
+    // Byte range in the source, from the end of the previous marker to the end of this one
+    pub bytes: Range<usize>,
+    // Plain text, with lines and pieces of lines joined by spaces
+    pub text: String,
+}
+
+#[derive(Clone, Debug)]
+pub struct PageIndex<'a> {
+    pub pages: Vec<Page<'a>>,
+}
+
+// Volume and page numbers are zero-padded, to different widths in different texts
+fn same_number(a: &str, b: &str) -> bool {
+    a.trim_start_matches('0') == b.trim_start_matches('0')
+}
+
+impl<'a> PageIndex<'a> {
+    #[must_use]
+    pub fn new(doc: &'a Document<'a>) -> Self {
+        let content = &doc.content;
+
+        let mut pages = Vec::new();
+        let mut text = String::new();
+        // Where the page being read started, as an item and as a byte offset
+        let mut first = 0;
+        let mut start = content.first().map_or(0, |item| item.span().start);
+
+        let mut close = |number: &'a PageNumber<'a>, index: usize, text: &mut String| {
+            pages.push(Page {
+                number,
+                range: first..index + 1,
+                content: &content[first..=index],
+                bytes: start..number.span.end,
+                text: std::mem::take(text),
+            });
+
+            // The rest of a line goes on the next page; a marker on a line of its own
+            // doesn't
+            first = match content[index] {
+                Content::PageNumber(_) => index + 1,
+                _ => index,
+            };
+            start = number.span.end;
+        };
+
+        for (i, item) in content.iter().enumerate() {
+            match item {
+                Content::Line(line) => {
+                    for part in &line.parts {
+                        match part {
+                            LinePart::TextPart { text: part, .. } => push_text(&mut text, part),
+                            LinePart::NamedEntityText {
+                                prefix, text: part, ..
+                            } => push_text(&mut text, &format!("{prefix}{part}")),
+                            LinePart::PageNumber(number) => close(number, i, &mut text),
+                            _ => {}
+                        }
+                    }
+                }
+                Content::SectionHeader { value, .. } => push_text(&mut text, value),
+                Content::PageNumber(number) => close(number, i, &mut text),
+                _ => {}
+            }
+        }
+
+        Self { pages }
+    }
+
+    /// The page with the given volume and page number, e.g., `get("1", "5")` for
+    /// PageV01P005. Leading zeros don't matter. If the number occurs more than once (as
+    /// V00P000 often does, for front matter), this is the first one
+    #[must_use]
+    pub fn get(&self, vol: &str, page: &str) -> Option<&Page<'a>> {
+        self.pages
+            .iter()
+            .find(|p| same_number(&p.number.vol, vol) && same_number(&p.number.page, page))
+    }
+
+    /// Pages in document order
+    pub fn iter(&self) -> std::slice::Iter<'_, Page<'a>> {
+        self.pages.iter()
+    }
+
+    /// The page that a byte offset in the source falls on
+    #[must_use]
+    pub fn page_at(&self, offset: usize) -> Option<&Page<'a>> {
+        let i = self.pages.partition_point(|page| page.bytes.end <= offset);
+
+        self.pages
+            .get(i)
+            .filter(|page| page.bytes.contains(&offset))
+    }
+
+    /// The page that a content item (by index) starts on
+    #[must_use]
+    pub fn page_of(&self, doc: &Document, index: usize) -> Option<&Page<'a>> {
+        self.page_at(doc.content.get(index)?.span().start)
+    }
+}
+
+impl<'a, 'b> IntoIterator for &'b PageIndex<'a> {
+    type Item = &'b Page<'a>;
+    type IntoIter = std::slice::Iter<'b, Page<'a>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.pages.iter()
+    }
+}
+
+impl<'a> Document<'a> {
+    #[must_use]
+    pub fn pages(&'a self) -> PageIndex<'a> {
+        PageIndex::new(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser;
+
+    #[test]
+    fn pages() {
+        let input = "######OpenITI#\n#META#Header#End#\n# قال ابن هشام\n~~حدثنا زياد PageV01P001 عن محمد\n# ثم فصل آخر\nPageV01P002\n# بقية الكلام";
+        let doc = parser(input).unwrap();
+        let index = doc.pages();
+
+        assert_eq!(index.pages.len(), 2);
+
+        let first = index.get("1", "1").unwrap();
+        assert_eq!(first.number.page, "001");
+        assert_eq!(first.text, "قال ابن هشام حدثنا زياد");
+        assert_eq!(
+            &input[first.bytes.clone()],
+            "# قال ابن هشام\n~~حدثنا زياد PageV01P001"
+        );
+
+        let second = index.get("01", "002").unwrap();
+        assert_eq!(second.text, "عن محمد ثم فصل آخر");
+        assert_eq!(second.range.start, first.range.end - 1);
+        assert!(matches!(
+            second.content.last(),
+            Some(Content::PageNumber(_))
+        ));
+
+        assert!(index.get("1", "3").is_none());
+
+        // Mapping back
+        let offset = input.find("محمد").unwrap();
+        assert_eq!(index.page_at(offset).unwrap().number.page, "002");
+        assert_eq!(index.page_of(&doc, 0).unwrap().number.page, "001");
+        assert!(index.page_of(&doc, doc.content.len() - 1).is_none());
+
+        let numbers: Vec<&str> = index.iter().map(|page| page.number.page.as_ref()).collect();
+        assert_eq!(numbers, ["001", "002"]);
+    }
+
+    #[test]
+    fn whole_file() {
+        let full_text = std::fs::read_to_string("test.md").unwrap();
+        let doc = parser(&full_text).unwrap();
+        let index = doc.pages();
+
+        assert!(index.pages.len() > 1000);
+
+        // Every page starts where the one before it ended
+        for pair in index.pages.windows(2) {
+            assert_eq!(pair[0].bytes.end, pair[1].bytes.start);
+            assert!(pair[0].range.end >= pair[1].range.start);
+        }
+
+        let page = index.get("2", "671").unwrap();
+        assert!(!page.text.is_empty());
+
+        for (i, item) in doc.content.iter().enumerate().step_by(97) {
+            if let Some(page) = index.page_of(&doc, i) {
+                assert!(page.range.contains(&i), "{i}");
+                assert!(page.bytes.contains(&item.span().start));
+            }
+        }
+    }
+}
@@ -0,0 +1,212 @@
+use crate::{
+    error::ParseError, offset_in, parse_source_line, stream::parse_file, structures::*,
+    tags::META_END, Event, LineCtx, ParserState,
+};
+use rayon::prelude::*;
+use std::ops::Range;
+use std::path::Path;
+
+// Parallel parsing
+// Two flavors: many files at once, one per task, and one big file cut into chunks of lines.
+// Both run on rayon's global thread pool (or whichever pool they're called from, via
+// ThreadPool::install)
+
+/// Parse a set of files concurrently. Results come back in the same order as the paths
+///
+/// # Errors
+///
+/// Each file gets its own result, with the same errors as [`parse_file`].
+pub fn parse_files<P: AsRef<Path> + Sync>(
+    paths: &[P],
+) -> Vec<Result<Document<'static>, ParseError>> {
+    paths.par_iter().map(parse_file).collect()
+}
+
+// Fewer lines than this aren't worth handing to another thread
+const MIN_CHUNK: usize = 256;
+
+// Where to cut the body into chunks. We only cut in front of a marker line (`#`, `###`,
+// etc.), so that a paragraph never gets split from its `~~` continuations, which may finish
+// a named entity it started
+fn chunk_ranges(lines: &[&str], body_start: usize) -> Vec<Range<usize>> {
+    let mut ranges = Vec::new();
+    ranges.push(0..body_start);
+
+    let target = ((lines.len() - body_start) / (rayon::current_num_threads() * 4)).max(MIN_CHUNK);
+    let mut start = body_start;
+
+    while start < lines.len() {
+        let mut end = (start + target).min(lines.len());
+
+        while end < lines.len() && !lines[end].trim_start().starts_with('#') {
+            end += 1;
+        }
+
+        ranges.push(start..end);
+        start = end;
+    }
+
+    ranges
+}
+
+// The parallel version of the main loop in parser_with_options. Returns the state after the
+// last line, as the sequential loop would have left it
+pub fn parse_lines_parallel<'a>(
+    input: &'a str,
+    doc: &mut Document<'a>,
+) -> Result<ParserState, ParseError> {
+    let lines: Vec<&str> = input.lines().collect();
+
+    // The state carried from line to line is where the header ended, which is cheap to find
+    // up front, and any named entity that runs on into the next line, which never gets past
+    // a marker line. Line 1 is never the end of the header (it has to be the magic value)
+    let header_end = lines
+        .iter()
+        .skip(1)
+        .position(|line| line.trim() == META_END)
+        .map(|i| i + 2);
+
+    // The header goes in a chunk of its own
+    let body_start = header_end.unwrap_or(0).min(lines.len());
+
+    let chunks: Vec<Result<(Vec<Event<'a>>, ParserState), ParseError>> =
+        chunk_ranges(&lines, body_start)
+            .into_par_iter()
+            .map(|range| {
+                // What the state would be, coming into this chunk from the previous lines
+                // A chunk starts at a marker line, so no named entity runs on into it
+                let mut state = ParserState {
+                    header_end: header_end.filter(|&end| end <= range.start),
+                    entity: None,
+                };
+                let mut events = Vec::new();
+
+                for i in range {
+                    let ctx = LineCtx {
+                        number: i + 1,
+                        start: offset_in(input, lines[i]).unwrap_or(0),
+                        raw: lines[i],
+                    };
+
+                    parse_source_line(ctx, &mut state, &mut events)?;
+                }
+
+                Ok((events, state))
+            })
+            .collect();
+
+    // Merge in order. If several chunks failed, the earliest error is the one that the
+    // sequential parser would have run into
+    let mut state = ParserState::default();
+
+    for chunk in chunks {
+        let (events, chunk_state) = chunk?;
+
+        for event in events {
+            doc.push_event(event);
+        }
+
+        state = chunk_state;
+    }
+
+    Ok(state)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parser, parser_with_options, ParseOptions};
+    use std::fmt::Write;
+    use std::fs;
+
+    const PARALLEL: ParseOptions = ParseOptions {
+        lossless: false,
+        parallel: true,
+        strict: false,
+    };
+
+    #[test]
+    fn same_as_parser() {
+        for path in ["test.md", "test2.md"] {
+            let full_text = fs::read_to_string(path).unwrap();
+
+            assert_eq!(
+                parser_with_options(&full_text, PARALLEL).unwrap(),
+                parser(&full_text).unwrap()
+            );
+
+            let lossless = ParseOptions {
+                lossless: true,
+                ..PARALLEL
+            };
+            assert_eq!(
+                parser_with_options(&full_text, lossless).unwrap(),
+                parser_with_options(
+                    &full_text,
+                    ParseOptions {
+                        lossless: true,
+                        ..Default::default()
+                    }
+                )
+                .unwrap()
+            );
+        }
+
+        // Stray metadata after the header, and a header that never ends
+        let mut input =
+            String::from("######OpenITI#\n#META# 000.Key :: value\n#META#Header#End#\n");
+        for i in 0..2000 {
+            writeln!(input, "# para {i} PageV01P{i:03}\n~~more").unwrap();
+        }
+        input.push_str("#META# 001.Key :: stray\n");
+
+        for input in [input.clone(), input.replace(META_END, "# no end")] {
+            assert_eq!(
+                parser_with_options(&input, PARALLEL).unwrap(),
+                parser(&input).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn malformed_input() {
+        let mut input = String::from("######OpenITI#\n#META#Header#End#\n");
+        for i in 0..2000 {
+            writeln!(input, "# para {i}").unwrap();
+        }
+
+        let mut bad = input.clone();
+        bad.push_str("# @PER9x\n");
+        let mut worse = input.clone();
+        worse.insert_str(input.find("# para 1000").unwrap(), "#META# no separator\n");
+        worse.push_str("# @PER9x\n");
+
+        // Diagnostics come out in the same order, and so does the error, if there is one
+        for input in [bad, worse, "not markdown".into()] {
+            assert_eq!(parser_with_options(&input, PARALLEL), parser(&input));
+        }
+    }
+
+    #[test]
+    fn files() {
+        let results = parse_files(&["test2.md", "missing.md", "test.md"]);
+
+        assert_eq!(results.len(), 3);
+        assert_eq!(
+            results[0].as_ref().unwrap(),
+            &parser(&fs::read_to_string("test2.md").unwrap()).unwrap()
+        );
+        assert!(matches!(
+            results[1],
+            Err(ParseError::Io {
+                line: 0,
+                kind: std::io::ErrorKind::NotFound,
+                ..
+            })
+        ));
+        assert_eq!(
+            results[2].as_ref().unwrap(),
+            &parser(&fs::read_to_string("test.md").unwrap()).unwrap()
+        );
+    }
+}
@@ -0,0 +1,226 @@
+use crate::structures::*;
+use std::ops::Range;
+
+// A tree view over the flat content of a Document, built from SectionHeader levels.
+// A header owns everything up to the next header of the same or a higher level (i.e.,
+// a lower number); headers of lower levels in between become its children. When levels are
+// skipped (e.g., ### | followed directly by ### |||), the deeper header just nests one step
+
+#[derive(Clone, Debug)]
+pub struct SectionTree<'a> {
+    // Content before the first header
+    pub preamble: &'a [Content<'a>],
+    pub sections: Vec<Section<'a>>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Section<'a> {
+    pub title: &'a str,
+    pub level: u32,
+    // Position among siblings at each depth, 1-based, e.g., [1, 3, 2]
+    pub path: Vec<usize>,
+    // Index of the header in the document's content
+    pub index: usize,
+    // Content between this header and the next one, of whatever level
+    pub content: &'a [Content<'a>],
+    // Content of this section and all of its subsections, as indices into the document
+    pub range: Range<usize>,
+    pub children: Vec<Self>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TocEntry {
+    pub path: String,
+    pub title: String,
+    pub level: u32,
+    pub depth: usize,
+    pub index: usize,
+}
+
+impl Section<'_> {
+    /// Section path in dotted form, e.g., "1.3.2"
+    #[must_use]
+    pub fn path_string(&self) -> String {
+        let numbers: Vec<String> = self.path.iter().map(ToString::to_string).collect();
+        numbers.join(".")
+    }
+}
+
+impl<'a> SectionTree<'a> {
+    #[must_use]
+    pub fn new(doc: &'a Document<'a>) -> Self {
+        let content = &doc.content;
+
+        let headers: Vec<(usize, &str, u32)> = content
+            .iter()
+            .enumerate()
+            .filter_map(|(i, item)| match item {
+                Content::SectionHeader { value, level, .. } => Some((i, value.as_ref(), *level)),
+                _ => None,
+            })
+            .collect();
+
+        let preamble_end = headers.first().map_or(content.len(), |header| header.0);
+
+        // Build the tree with a stack of open sections, closing them as we go
+        let mut sections: Vec<Section> = Vec::new();
+        let mut stack: Vec<Section> = Vec::new();
+
+        for (n, &(index, title, level)) in headers.iter().enumerate() {
+            let next_header = headers.get(n + 1).map_or(content.len(), |header| header.0);
+
+            while stack.last().is_some_and(|open| open.level >= level) {
+                close(&mut stack, &mut sections, index);
+            }
+
+            let siblings = stack
+                .last()
+                .map_or(sections.len(), |parent| parent.children.len());
+
+            let mut path = stack
+                .last()
+                .map_or_else(Vec::new, |parent| parent.path.clone());
+            path.push(siblings + 1);
+
+            stack.push(Section {
+                title,
+                level,
+                path,
+                index,
+                content: &content[index + 1..next_header],
+                range: index..content.len(),
+                children: Vec::new(),
+            });
+        }
+
+        while !stack.is_empty() {
+            close(&mut stack, &mut sections, content.len());
+        }
+
+        Self {
+            preamble: &content[..preamble_end],
+            sections,
+        }
+    }
+
+    /// The chain of sections, outermost first, that contains the content item at the given
+    /// index. A header counts as being inside its own section
+    #[must_use]
+    pub fn find(&self, index: usize) -> Vec<&Section<'a>> {
+        let mut chain = Vec::new();
+        let mut level = &self.sections;
+
+        while let Some(section) = level.iter().find(|section| section.range.contains(&index)) {
+            chain.push(section);
+            level = &section.children;
+        }
+
+        chain
+    }
+
+    /// Table of contents, in document order
+    #[must_use]
+    pub fn toc(&self) -> Vec<TocEntry> {
+        let mut entries = Vec::new();
+
+        for section in &self.sections {
+            push_toc(section, &mut entries);
+        }
+
+        entries
+    }
+}
+
+// Pop the innermost open section, now that we know where it ends, and attach it to its parent
+fn close<'a>(stack: &mut Vec<Section<'a>>, sections: &mut Vec<Section<'a>>, end: usize) {
+    if let Some(mut section) = stack.pop() {
+        section.range.end = end;
+
+        match stack.last_mut() {
+            Some(parent) => parent.children.push(section),
+            None => sections.push(section),
+        }
+    }
+}
+
+fn push_toc(section: &Section, entries: &mut Vec<TocEntry>) {
+    entries.push(TocEntry {
+        path: section.path_string(),
+        title: section.title.into(),
+        level: section.level,
+        depth: section.path.len(),
+        index: section.index,
+    });
+
+    for child in &section.children {
+        push_toc(child, entries);
+    }
+}
+
+impl Document<'_> {
+    #[must_use]
+    pub fn sections(&self) -> SectionTree<'_> {
+        SectionTree::new(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parser;
+
+    #[test]
+    fn nesting() {
+        let input = "######OpenITI#\n# before\n### | one\n### || one.one\n~~text\n### |||| skipped\n### || one.two\n### | two\n### ||| two.one\n# after";
+        let doc = parser(input).unwrap();
+        let tree = doc.sections();
+
+        assert_eq!(tree.preamble.len(), 2);
+        assert_eq!(tree.sections.len(), 2);
+
+        let toc: Vec<(String, &str)> = tree
+            .toc()
+            .into_iter()
+            .map(|entry| {
+                (
+                    entry.path,
+                    &doc.content[entry.index].as_section_header().unwrap().1[..],
+                )
+            })
+            .collect();
+
+        assert_eq!(
+            toc,
+            [
+                ("1".into(), "one"),
+                ("1.1".into(), "one.one"),
+                ("1.1.1".into(), "skipped"),
+                ("1.2".into(), "one.two"),
+                ("2".into(), "two"),
+                ("2.1".into(), "two.one"),
+            ]
+        );
+
+        let one = &tree.sections[0];
+        assert_eq!(one.range, 2..7);
+        assert!(one.content.is_empty());
+        assert_eq!(one.children[0].content.len(), 1);
+
+        // The trailing paragraph belongs to the innermost open section
+        let chain: Vec<String> = tree.find(10).iter().map(|s| s.path_string()).collect();
+        assert_eq!(chain, ["2", "2.1"]);
+        assert!(tree.find(0).is_empty());
+    }
+
+    #[test]
+    fn sections_in_file() {
+        let full_text = std::fs::read_to_string("test.md").unwrap();
+        let doc = parser(&full_text).unwrap();
+        let tree = doc.sections();
+
+        // Every header shows up in the TOC exactly once, in order
+        let toc = tree.toc();
+        let headers = doc.content.iter().filter(|c| c.is_section_header()).count();
+        assert_eq!(toc.len(), headers);
+        assert!(toc.windows(2).all(|pair| pair[0].index < pair[1].index));
+    }
+}
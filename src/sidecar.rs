@@ -0,0 +1,657 @@
+use crate::{
+    corpus::CorpusText, error::SidecarError, metadata::Metadata, structures::*,
+    tokens::arabic_tokens, uri::Uri,
+};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+// YAML sidecars
+// Each author, book, and version in the corpus has a `.yml` file next to it with catalogue
+// data. The keys are fixed-width, padded with `#` (e.g., `00#VERS#LENGTH###`), and values
+// can run on over indented lines. That's about all the YAML there is to them, so we read
+// them line by line rather than pulling in a YAML library
+
+const AUTH_URI: &str = "00#AUTH#URI######";
+const AUTH_ISM: &str = "10#AUTH#ISM####AR";
+const AUTH_KUNYA: &str = "10#AUTH#KUNYA##AR";
+const AUTH_LAQAB: &str = "10#AUTH#LAQAB##AR";
+const AUTH_NASAB: &str = "10#AUTH#NASAB##AR";
+const AUTH_NISBA: &str = "10#AUTH#NISBA##AR";
+const AUTH_SHUHRA: &str = "10#AUTH#SHUHRA#AR";
+const AUTH_BORN: &str = "20#AUTH#BORN#####";
+const AUTH_DIED: &str = "20#AUTH#DIED#####";
+const AUTH_COMMENT: &str = "90#AUTH#COMMENT##";
+
+const BOOK_URI: &str = "00#BOOK#URI######";
+const BOOK_GENRES: &str = "10#BOOK#GENRES###";
+const BOOK_TITLE_A: &str = "10#BOOK#TITLEA#AR";
+const BOOK_TITLE_B: &str = "10#BOOK#TITLEB#AR";
+const BOOK_WROTE: &str = "20#BOOK#WROTE####";
+const BOOK_ORIG_LANG: &str = "30#BOOK#ORIG#LANG";
+const BOOK_RELATED: &str = "40#BOOK#RELATED##";
+const BOOK_COMMENT: &str = "90#BOOK#COMMENT##";
+
+const VERS_URI: &str = "00#VERS#URI######";
+const VERS_LENGTH: &str = "00#VERS#LENGTH###";
+const VERS_CLENGTH: &str = "00#VERS#CLENGTH##";
+const VERS_BASED: &str = "80#VERS#BASED####";
+const VERS_COLLATED: &str = "80#VERS#COLLATED#";
+const VERS_LINKS: &str = "80#VERS#LINKS####";
+const VERS_ANNOTATOR: &str = "90#VERS#ANNOTATOR";
+const VERS_COMMENT: &str = "90#VERS#COMMENT##";
+const VERS_DATE: &str = "90#VERS#DATE#####";
+const VERS_ISSUES: &str = "90#VERS#ISSUES###";
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SidecarEntry {
+    pub key: String,
+    pub value: Option<String>,
+}
+
+// The raw file: entries in order, with whatever keys it has
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Sidecar {
+    pub entries: Vec<SidecarEntry>,
+}
+
+impl Sidecar {
+    /// # Errors
+    ///
+    /// Will return an error if a line is neither a `key: value` pair nor the continuation of
+    /// one.
+    pub fn parse(text: &str) -> Result<Self, SidecarError> {
+        let mut entries: Vec<SidecarEntry> = Vec::new();
+
+        for (i, line) in text.lines().enumerate() {
+            let trimmed = line.trim();
+
+            // Blank lines, YAML comments, and the document marker
+            if trimmed.is_empty() || trimmed.starts_with("# ") || trimmed == "---" {
+                continue;
+            }
+
+            // Indented lines carry on the value above
+            if line.starts_with(char::is_whitespace) {
+                if let Some(entry) = entries.last_mut() {
+                    match &mut entry.value {
+                        Some(value) => {
+                            value.push(' ');
+                            value.push_str(trimmed);
+                        }
+                        None => entry.value = Some(trimmed.into()),
+                    }
+                    continue;
+                }
+            }
+
+            let Some((key, value)) = line.split_once(':').filter(|(key, _)| !key.is_empty()) else {
+                return Err(SidecarError::BadLine {
+                    line: i + 1,
+                    text: line.into(),
+                });
+            };
+
+            let value = value.trim();
+
+            entries.push(SidecarEntry {
+                key: key.trim().into(),
+                value: (!value.is_empty()).then(|| value.into()),
+            });
+        }
+
+        Ok(Self { entries })
+    }
+
+    /// # Errors
+    ///
+    /// Same as [`Sidecar::parse`], plus [`SidecarError::Io`] if the file can't be read.
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<Self, SidecarError> {
+        let text = fs::read_to_string(path).map_err(|err| SidecarError::Io {
+            kind: err.kind(),
+            message: err.to_string(),
+        })?;
+
+        Self::parse(&text)
+    }
+
+    /// # Errors
+    ///
+    /// Will return an error if the file can't be written.
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        fs::write(path, self.to_string())
+    }
+
+    #[must_use]
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.entries
+            .iter()
+            .find(|entry| entry.key == key)
+            .and_then(|entry| entry.value.as_deref())
+    }
+
+    // Lists (genres, issues, etc.) are comma-separated
+    fn list(&self, key: &str) -> Vec<String> {
+        self.get(key)
+            .map(|value| {
+                value
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|item| !item.is_empty())
+                    .map(Into::into)
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    fn number(&self, key: &str) -> Option<u32> {
+        self.get(key)?.parse().ok()
+    }
+
+    fn uri(&self, key: &str) -> Result<Option<Uri>, SidecarError> {
+        self.get(key)
+            .map(|value| value.parse().map_err(SidecarError::BadUri))
+            .transpose()
+    }
+
+    // Everything but the keys that the typed structs know about
+    fn other(&self, known: &[&str]) -> Vec<SidecarEntry> {
+        self.entries
+            .iter()
+            .filter(|entry| !known.contains(&entry.key.as_str()))
+            .cloned()
+            .collect()
+    }
+
+    fn push(&mut self, key: &str, value: Option<String>) {
+        self.entries.push(SidecarEntry {
+            key: key.into(),
+            value,
+        });
+    }
+
+    fn push_list(&mut self, key: &str, items: &[String]) {
+        self.push(key, (!items.is_empty()).then(|| items.join(", ")));
+    }
+}
+
+// One `key: value` line per entry. Empty values are written as a bare key, the way the
+// templates in the corpus have them
+impl fmt::Display for Sidecar {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for entry in &self.entries {
+            match &entry.value {
+                Some(value) => writeln!(f, "{}: {value}", entry.key)?,
+                None => writeln!(f, "{}:", entry.key)?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// The typed versions. Keys they don't know about are kept in `other`, and written back
+// after the known ones
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct AuthorSidecar {
+    pub uri: Option<Uri>,
+    pub ism: Option<String>,
+    pub kunya: Option<String>,
+    pub laqab: Option<String>,
+    pub nasab: Option<String>,
+    pub nisba: Option<String>,
+    pub shuhra: Option<String>,
+    // Dates are AH
+    pub born: Option<u32>,
+    pub died: Option<u32>,
+    pub comment: Option<String>,
+    pub other: Vec<SidecarEntry>,
+}
+
+const AUTH_KEYS: [&str; 10] = [
+    AUTH_URI,
+    AUTH_ISM,
+    AUTH_KUNYA,
+    AUTH_LAQAB,
+    AUTH_NASAB,
+    AUTH_NISBA,
+    AUTH_SHUHRA,
+    AUTH_BORN,
+    AUTH_DIED,
+    AUTH_COMMENT,
+];
+
+impl TryFrom<&Sidecar> for AuthorSidecar {
+    type Error = SidecarError;
+
+    fn try_from(sidecar: &Sidecar) -> Result<Self, Self::Error> {
+        let text = |key| sidecar.get(key).map(Into::into);
+
+        Ok(Self {
+            uri: sidecar.uri(AUTH_URI)?,
+            ism: text(AUTH_ISM),
+            kunya: text(AUTH_KUNYA),
+            laqab: text(AUTH_LAQAB),
+            nasab: text(AUTH_NASAB),
+            nisba: text(AUTH_NISBA),
+            shuhra: text(AUTH_SHUHRA),
+            born: sidecar.number(AUTH_BORN),
+            died: sidecar.number(AUTH_DIED),
+            comment: text(AUTH_COMMENT),
+            other: sidecar.other(&AUTH_KEYS),
+        })
+    }
+}
+
+impl From<&AuthorSidecar> for Sidecar {
+    fn from(author: &AuthorSidecar) -> Self {
+        let mut sidecar = Self::default();
+
+        sidecar.push(AUTH_URI, author.uri.as_ref().map(ToString::to_string));
+        sidecar.push(AUTH_ISM, author.ism.clone());
+        sidecar.push(AUTH_KUNYA, author.kunya.clone());
+        sidecar.push(AUTH_LAQAB, author.laqab.clone());
+        sidecar.push(AUTH_NASAB, author.nasab.clone());
+        sidecar.push(AUTH_NISBA, author.nisba.clone());
+        sidecar.push(AUTH_SHUHRA, author.shuhra.clone());
+        // Dates are written with four digits, as in URIs
+        sidecar.push(AUTH_BORN, author.born.map(|date| format!("{date:04}")));
+        sidecar.push(AUTH_DIED, author.died.map(|date| format!("{date:04}")));
+        sidecar.push(AUTH_COMMENT, author.comment.clone());
+        sidecar.entries.extend(author.other.iter().cloned());
+
+        sidecar
+    }
+}
+
+impl AuthorSidecar {
+    /// Fill in whatever the sidecar leaves out from a text's header
+    pub fn merge_metadata(&mut self, metadata: &Metadata) {
+        if let Some(Ok(uri)) = metadata.uri() {
+            self.uri.get_or_insert_with(|| uri.author_uri());
+        }
+
+        self.born = self
+            .born
+            .or_else(|| metadata.get("AuthorBORN")?.parse().ok());
+        self.died = self.died.or_else(|| metadata.author_died());
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct BookSidecar {
+    pub uri: Option<Uri>,
+    pub genres: Vec<String>,
+    pub title_a: Option<String>,
+    pub title_b: Option<String>,
+    pub wrote: Option<String>,
+    pub orig_lang: Option<String>,
+    pub related: Vec<String>,
+    pub comment: Option<String>,
+    pub other: Vec<SidecarEntry>,
+}
+
+const BOOK_KEYS: [&str; 8] = [
+    BOOK_URI,
+    BOOK_GENRES,
+    BOOK_TITLE_A,
+    BOOK_TITLE_B,
+    BOOK_WROTE,
+    BOOK_ORIG_LANG,
+    BOOK_RELATED,
+    BOOK_COMMENT,
+];
+
+impl TryFrom<&Sidecar> for BookSidecar {
+    type Error = SidecarError;
+
+    fn try_from(sidecar: &Sidecar) -> Result<Self, Self::Error> {
+        let text = |key| sidecar.get(key).map(Into::into);
+
+        Ok(Self {
+            uri: sidecar.uri(BOOK_URI)?,
+            genres: sidecar.list(BOOK_GENRES),
+            title_a: text(BOOK_TITLE_A),
+            title_b: text(BOOK_TITLE_B),
+            wrote: text(BOOK_WROTE),
+            orig_lang: text(BOOK_ORIG_LANG),
+            related: sidecar.list(BOOK_RELATED),
+            comment: text(BOOK_COMMENT),
+            other: sidecar.other(&BOOK_KEYS),
+        })
+    }
+}
+
+impl From<&BookSidecar> for Sidecar {
+    fn from(book: &BookSidecar) -> Self {
+        let mut sidecar = Self::default();
+
+        sidecar.push(BOOK_URI, book.uri.as_ref().map(ToString::to_string));
+        sidecar.push_list(BOOK_GENRES, &book.genres);
+        sidecar.push(BOOK_TITLE_A, book.title_a.clone());
+        sidecar.push(BOOK_TITLE_B, book.title_b.clone());
+        sidecar.push(BOOK_WROTE, book.wrote.clone());
+        sidecar.push(BOOK_ORIG_LANG, book.orig_lang.clone());
+        sidecar.push_list(BOOK_RELATED, &book.related);
+        sidecar.push(BOOK_COMMENT, book.comment.clone());
+        sidecar.entries.extend(book.other.iter().cloned());
+
+        sidecar
+    }
+}
+
+impl BookSidecar {
+    /// Fill in whatever the sidecar leaves out from a text's header
+    pub fn merge_metadata(&mut self, metadata: &Metadata) {
+        if let Some(Ok(uri)) = metadata.uri() {
+            if let Some(book_uri) = uri.book_uri() {
+                self.uri.get_or_insert(book_uri);
+            }
+        }
+
+        if self.title_a.is_none() {
+            self.title_a = metadata.book_title().map(Into::into);
+        }
+
+        if self.title_b.is_none() {
+            self.title_b = metadata.get("BookTITLEalt").map(Into::into);
+        }
+
+        if self.orig_lang.is_none() {
+            self.orig_lang = metadata.get("BookLANG").map(Into::into);
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct VersionSidecar {
+    pub uri: Option<Uri>,
+    // Number of Arabic-script words, and of characters
+    pub length: Option<u32>,
+    pub char_length: Option<u32>,
+    pub based: Vec<String>,
+    pub collated: Vec<String>,
+    pub links: Vec<String>,
+    pub annotator: Option<String>,
+    pub comment: Option<String>,
+    pub date: Option<String>,
+    pub issues: Vec<String>,
+    pub other: Vec<SidecarEntry>,
+}
+
+const VERS_KEYS: [&str; 10] = [
+    VERS_URI,
+    VERS_LENGTH,
+    VERS_CLENGTH,
+    VERS_BASED,
+    VERS_COLLATED,
+    VERS_LINKS,
+    VERS_ANNOTATOR,
+    VERS_COMMENT,
+    VERS_DATE,
+    VERS_ISSUES,
+];
+
+impl TryFrom<&Sidecar> for VersionSidecar {
+    type Error = SidecarError;
+
+    fn try_from(sidecar: &Sidecar) -> Result<Self, Self::Error> {
+        let text = |key| sidecar.get(key).map(Into::into);
+
+        Ok(Self {
+            uri: sidecar.uri(VERS_URI)?,
+            length: sidecar.number(VERS_LENGTH),
+            char_length: sidecar.number(VERS_CLENGTH),
+            based: sidecar.list(VERS_BASED),
+            collated: sidecar.list(VERS_COLLATED),
+            links: sidecar.list(VERS_LINKS),
+            annotator: text(VERS_ANNOTATOR),
+            comment: text(VERS_COMMENT),
+            date: text(VERS_DATE),
+            issues: sidecar.list(VERS_ISSUES),
+            other: sidecar.other(&VERS_KEYS),
+        })
+    }
+}
+
+impl From<&VersionSidecar> for Sidecar {
+    fn from(version: &VersionSidecar) -> Self {
+        let mut sidecar = Self::default();
+
+        sidecar.push(VERS_URI, version.uri.as_ref().map(ToString::to_string));
+        sidecar.push(VERS_LENGTH, version.length.map(|n| n.to_string()));
+        sidecar.push(VERS_CLENGTH, version.char_length.map(|n| n.to_string()));
+        sidecar.push_list(VERS_BASED, &version.based);
+        sidecar.push_list(VERS_COLLATED, &version.collated);
+        sidecar.push_list(VERS_LINKS, &version.links);
+        sidecar.push(VERS_ANNOTATOR, version.annotator.clone());
+        sidecar.push(VERS_COMMENT, version.comment.clone());
+        sidecar.push(VERS_DATE, version.date.clone());
+        sidecar.push_list(VERS_ISSUES, &version.issues);
+        sidecar.entries.extend(version.other.iter().cloned());
+
+        sidecar
+    }
+}
+
+impl VersionSidecar {
+    /// Fill in the lengths, if the sidecar doesn't have them, by counting the words and
+    /// characters of the text (tags left out), in Arabic tokens (see [`arabic_tokens`])
+    pub fn merge_document(&mut self, doc: &Document) {
+        if self.length.is_some() && self.char_length.is_some() {
+            return;
+        }
+
+        let mut words = 0;
+        let mut chars = 0;
+
+        for item in &doc.content {
+            let Content::Line(Line {
+                text_only: Some(text),
+                ..
+            }) = item
+            else {
+                continue;
+            };
+
+            for token in arabic_tokens(text) {
+                words += 1;
+                chars += token.chars().count();
+            }
+        }
+
+        self.length = self.length.or_else(|| u32::try_from(words).ok());
+        self.char_length = self.char_length.or_else(|| u32::try_from(chars).ok());
+    }
+}
+
+// The three sidecars of a text. Any of them might be missing from the corpus
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Sidecars {
+    pub author: Option<AuthorSidecar>,
+    pub book: Option<BookSidecar>,
+    pub version: Option<VersionSidecar>,
+}
+
+impl Sidecars {
+    /// Fill the gaps in all three from a parsed text: its header, and, for the version, the
+    /// text itself. What the sidecars already say wins. Missing sidecars are created
+    pub fn merge(&mut self, doc: &Document) {
+        self.author
+            .get_or_insert_with(Default::default)
+            .merge_metadata(&doc.metadata);
+        self.book
+            .get_or_insert_with(Default::default)
+            .merge_metadata(&doc.metadata);
+
+        let version = self.version.get_or_insert_with(Default::default);
+        version.merge_document(doc);
+    }
+}
+
+// Read a sidecar if it's there
+fn read_optional<T>(path: &Path) -> Result<Option<T>, SidecarError>
+where
+    T: for<'s> TryFrom<&'s Sidecar, Error = SidecarError>,
+{
+    if !path.exists() {
+        return Ok(None);
+    }
+
+    T::try_from(&Sidecar::read(path)?).map(Some)
+}
+
+impl CorpusText {
+    /// Read the author, book, and version sidecars, which live in the author folder, the
+    /// book folder, and next to the text, respectively
+    ///
+    /// # Errors
+    ///
+    /// Will return an error if a sidecar is there but can't be read or parsed.
+    pub fn sidecars(&self) -> Result<Sidecars, SidecarError> {
+        let book_dir = self.path.parent().unwrap_or_else(|| Path::new(""));
+        let author_dir = book_dir.parent().unwrap_or_else(|| Path::new(""));
+
+        let yml = |dir: &Path, uri: &Uri| dir.join(format!("{uri}.yml"));
+
+        let mut sidecars = Sidecars {
+            author: read_optional(&yml(author_dir, &self.uri.author_uri()))?,
+            book: None,
+            version: read_optional(&yml(book_dir, &self.uri))?,
+        };
+
+        if let Some(book_uri) = self.uri.book_uri() {
+            sidecars.book = read_optional(&yml(book_dir, &book_uri))?;
+        }
+
+        Ok(sidecars)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{corpus_texts, parser};
+
+    const VERSION: &str = "00#VERS#LENGTH###: 113956
+00#VERS#URI######: 0213IbnHisham.SiraNabawiyya.Shamela0023833-ara1
+80#VERS#BASED####:
+80#VERS#COLLATED#:
+80#VERS#LINKS####:
+90#VERS#ANNOTATOR:
+90#VERS#COMMENT##: A long comment that
+    runs on over a second line
+90#VERS#DATE#####:
+90#VERS#ISSUES###: PRI, UNCORRECTED_OCR
+99#VERS#UNKNOWN##: kept
+";
+
+    #[test]
+    fn read_and_write() {
+        let sidecar = Sidecar::parse(VERSION).unwrap();
+        let version = VersionSidecar::try_from(&sidecar).unwrap();
+
+        assert_eq!(version.length, Some(113_956));
+        assert_eq!(
+            version.uri.as_ref().unwrap().to_string(),
+            "0213IbnHisham.SiraNabawiyya.Shamela0023833-ara1"
+        );
+        assert!(version.based.is_empty());
+        assert_eq!(
+            version.comment.as_deref(),
+            Some("A long comment that runs on over a second line")
+        );
+        assert_eq!(version.issues, ["PRI", "UNCORRECTED_OCR"]);
+        assert_eq!(version.other[0].key, "99#VERS#UNKNOWN##");
+
+        // Written back and read again, nothing is lost
+        let written = Sidecar::from(&version).to_string();
+        assert!(written.contains("90#VERS#ISSUES###: PRI, UNCORRECTED_OCR\n"));
+        assert!(written.contains("80#VERS#BASED####:\n"));
+
+        let reread = VersionSidecar::try_from(&Sidecar::parse(&written).unwrap()).unwrap();
+        assert_eq!(reread, version);
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(
+            Sidecar::parse("00#AUTH#URI######: 0213IbnHisham\nno separator"),
+            Err(SidecarError::BadLine {
+                line: 2,
+                text: "no separator".into()
+            })
+        );
+
+        let sidecar = Sidecar::parse("00#AUTH#URI######: IbnHisham").unwrap();
+        assert!(matches!(
+            AuthorSidecar::try_from(&sidecar),
+            Err(SidecarError::BadUri(_))
+        ));
+    }
+
+    #[test]
+    fn merge() {
+        let full_text = std::fs::read_to_string("test.md").unwrap();
+        let doc = parser(&full_text).unwrap();
+
+        let mut sidecars = Sidecars {
+            author: Some(
+                AuthorSidecar::try_from(
+                    &Sidecar::parse("20#AUTH#DIED#####: 0218\n10#AUTH#SHUHRA#AR: ابن هشام")
+                        .unwrap(),
+                )
+                .unwrap(),
+            ),
+            ..Default::default()
+        };
+        sidecars.merge(&doc);
+
+        // The sidecar wins where it has something to say
+        let author = sidecars.author.unwrap();
+        assert_eq!(author.died, Some(218));
+        assert_eq!(author.uri.unwrap().to_string(), "0213IbnHisham");
+
+        let book = sidecars.book.unwrap();
+        assert_eq!(book.uri.unwrap().to_string(), "0213IbnHisham.SiraNabawiyya");
+        assert_eq!(book.title_a.as_deref(), Some("السيرة النبوية لابن هشام"));
+
+        let version = sidecars.version.unwrap();
+        assert!(version.length.unwrap() > 100_000);
+        assert!(version.char_length > version.length);
+    }
+
+    #[test]
+    fn corpus() {
+        let root = std::env::temp_dir().join(format!("oimdp-sidecars-{}", std::process::id()));
+        let book = root.join("0213IbnHisham/0213IbnHisham.SiraNabawiyya");
+        fs::create_dir_all(&book).unwrap();
+
+        fs::copy(
+            "test.md",
+            book.join("0213IbnHisham.SiraNabawiyya.Shamela0023833-ara1.mARkdown"),
+        )
+        .unwrap();
+        fs::write(
+            book.join("0213IbnHisham.SiraNabawiyya.Shamela0023833-ara1.yml"),
+            VERSION,
+        )
+        .unwrap();
+        fs::write(
+            root.join("0213IbnHisham/0213IbnHisham.yml"),
+            "00#AUTH#URI######: 0213IbnHisham\n20#AUTH#DIED#####: 0213\n",
+        )
+        .unwrap();
+
+        let texts = corpus_texts(&root).unwrap();
+        let sidecars = texts[0].sidecars();
+        let _ = fs::remove_dir_all(&root);
+
+        let sidecars = sidecars.unwrap();
+        assert_eq!(sidecars.author.unwrap().died, Some(213));
+        assert_eq!(sidecars.book, None);
+        assert_eq!(sidecars.version.unwrap().length, Some(113_956));
+    }
+}
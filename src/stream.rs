@@ -0,0 +1,236 @@
+use crate::metadata::MetadataEntry;
+use crate::{error::ParseError, parse_source_line, structures::*, LineCtx, ParserState};
+use enum_as_inner::EnumAsInner;
+use std::borrow::Cow;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+
+// Streaming
+// A pull parser over any BufRead. It reads one line at a time and hands back what the line
+// turned into, so memory use depends on the longest line rather than on the whole file.
+// The per-line logic is the same as in parser; only the bookkeeping differs
+
+#[derive(Clone, Debug, PartialEq, Eq, EnumAsInner)]
+pub enum Event<'a> {
+    Magic(Cow<'a, str>),
+    // The metadata line without the #META# tag (what goes into simple_metadata), and the
+    // parsed entry, unless the line isn't a key-value pair
+    Metadata {
+        text: Cow<'a, str>,
+        entry: Option<MetadataEntry<'a>>,
+    },
+    Content(Content<'a>),
+    Diagnostic(Diagnostic),
+}
+
+impl Event<'_> {
+    #[must_use]
+    pub fn into_owned(self) -> Event<'static> {
+        match self {
+            Self::Magic(value) => Event::Magic(Cow::Owned(value.into_owned())),
+            Self::Metadata { text, entry } => Event::Metadata {
+                text: Cow::Owned(text.into_owned()),
+                entry: entry.map(MetadataEntry::into_owned),
+            },
+            Self::Content(item) => Event::Content(item.into_owned()),
+            Self::Diagnostic(diagnostic) => Event::Diagnostic(diagnostic),
+        }
+    }
+}
+
+pub struct StreamParser<R> {
+    reader: R,
+    buf: String,
+    // Number of the last line read, and byte offset of the next one
+    number: usize,
+    offset: usize,
+    state: ParserState,
+    // The line buffer gets reused, so what we hand out has to be owned
+    pending: VecDeque<Event<'static>>,
+    done: bool,
+}
+
+impl<R: BufRead> StreamParser<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            buf: String::new(),
+            number: 0,
+            offset: 0,
+            state: ParserState::default(),
+            pending: VecDeque::new(),
+            done: false,
+        }
+    }
+
+    // Read and parse the next line, queueing up its events. Returns false at the end of input
+    fn advance(&mut self) -> Result<bool, ParseError> {
+        self.buf.clear();
+
+        let read = self
+            .reader
+            .read_line(&mut self.buf)
+            .map_err(|err| ParseError::Io {
+                line: self.number + 1,
+                kind: err.kind(),
+                message: err.to_string(),
+            })?;
+
+        if read == 0 {
+            return Ok(false);
+        }
+
+        // Same line endings as str::lines
+        let raw = self.buf.strip_suffix('\n').unwrap_or(&self.buf);
+        let raw = raw.strip_suffix('\r').unwrap_or(raw);
+
+        self.number += 1;
+
+        let ctx = LineCtx {
+            number: self.number,
+            start: self.offset,
+            raw,
+        };
+
+        let mut events = Vec::new();
+        parse_source_line(ctx, &mut self.state, &mut events)?;
+
+        self.offset += read;
+        self.pending
+            .extend(events.into_iter().map(Event::into_owned));
+
+        Ok(true)
+    }
+}
+
+impl<R: BufRead> Iterator for StreamParser<R> {
+    type Item = Result<Event<'static>, ParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(event) = self.pending.pop_front() {
+                return Some(Ok(event));
+            }
+
+            if self.done {
+                return None;
+            }
+
+            // Blank lines and the like produce no events, so keep reading. After an error,
+            // we stop for good
+            match self.advance() {
+                Ok(true) => {}
+                Ok(false) => self.done = true,
+                Err(err) => {
+                    self.done = true;
+                    return Some(Err(err));
+                }
+            }
+        }
+    }
+}
+
+impl<'a> Document<'a> {
+    pub(crate) fn push_event(&mut self, event: Event<'a>) {
+        match event {
+            Event::Magic(value) => self.magic_value = value,
+            Event::Metadata { text, entry } => {
+                self.simple_metadata.push(text);
+                self.metadata.entries.extend(entry);
+            }
+            Event::Content(item) => self.content.push(item),
+            Event::Diagnostic(diagnostic) => self.diagnostics.push(diagnostic),
+        }
+    }
+}
+
+/// Parse a whole document from a reader. The result is the same as [`parser`](crate::parser)
+/// on the same text
+///
+/// # Errors
+///
+/// Same as [`parser`](crate::parser), plus [`ParseError::Io`] if reading fails (e.g., if the
+/// input isn't valid UTF-8).
+pub fn parse_reader<R: BufRead>(reader: R) -> Result<Document<'static>, ParseError> {
+    let mut doc = Document::default();
+
+    for event in StreamParser::new(reader) {
+        doc.push_event(event?);
+    }
+
+    Ok(doc)
+}
+
+/// Parse a whole document from a file, without reading it all into memory first
+///
+/// # Errors
+///
+/// Same as [`parse_reader`]. A file that can't be opened at all gives [`ParseError::Io`] on
+/// line 0.
+pub fn parse_file<P: AsRef<Path>>(path: P) -> Result<Document<'static>, ParseError> {
+    let file = File::open(path).map_err(|err| ParseError::Io {
+        line: 0,
+        kind: err.kind(),
+        message: err.to_string(),
+    })?;
+
+    parse_reader(BufReader::new(file))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser;
+    use std::io::Cursor;
+
+    #[test]
+    fn same_as_parser() {
+        let full_text = std::fs::read_to_string("test.md").unwrap();
+        let doc = parser(&full_text).unwrap();
+
+        // A tiny buffer, so that lines get split across reads
+        let reader = std::io::BufReader::with_capacity(16, full_text.as_bytes());
+        assert_eq!(parse_reader(reader).unwrap(), doc);
+
+        // Content comes out in order
+        let content: Vec<Content> = StreamParser::new(full_text.as_bytes())
+            .filter_map(|event| event.unwrap().into_content().ok())
+            .collect();
+        assert_eq!(content, doc.content);
+    }
+
+    #[test]
+    fn line_endings() {
+        let input = "######OpenITI#\r\n#META# 000.Key :: value\r\n#META#Header#End#\r\n\r\n# text PageV01\r\n~~more";
+        let doc = parser(input).unwrap();
+
+        assert_eq!(parse_reader(input.as_bytes()).unwrap(), doc);
+        assert_eq!(doc.diagnostics.len(), 1);
+    }
+
+    #[test]
+    fn errors() {
+        let mut events = StreamParser::new(&b"not markdown\n# text"[..]);
+        assert_eq!(
+            events.next(),
+            Some(Err(ParseError::BadMagic { line: 1, column: 1 }))
+        );
+        assert_eq!(events.next(), None);
+
+        // Invalid UTF-8 on the third line
+        let mut input = b"######OpenITI#\n# text\n".to_vec();
+        input.extend_from_slice(&[0xff, 0xfe, b'\n']);
+
+        let err = parse_reader(Cursor::new(input)).unwrap_err();
+        assert_eq!(err.line(), 3);
+        assert!(matches!(
+            err,
+            ParseError::Io {
+                kind: std::io::ErrorKind::InvalidData,
+                ..
+            }
+        ));
+    }
+}
@@ -1,41 +1,290 @@
+use crate::metadata::Metadata;
 use enum_as_inner::EnumAsInner;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::ops::Range;
 
 // This needs ongoing review; I obviously couldn't replicate Python objects one-to-one
 
 // Document
 
-#[derive(Debug)]
-pub struct Document {
-    pub magic_value: String,
-    pub simple_metadata: Vec<String>,
-    pub content: Vec<Content>,
+#[derive(Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Document<'a> {
+    pub magic_value: Cow<'a, str>,
+    pub simple_metadata: Vec<Cow<'a, str>>,
+    pub metadata: Metadata<'a>,
+    pub content: Vec<Content<'a>>,
+    pub diagnostics: Vec<Diagnostic>,
+    // Only kept in lossless mode
+    pub trivia: Option<Trivia>,
+}
+
+impl Document<'_> {
+    /// Copy out whatever still borrows from the input, so the document can outlive it
+    #[must_use]
+    pub fn into_owned(self) -> Document<'static> {
+        Document {
+            magic_value: owned(self.magic_value),
+            simple_metadata: self.simple_metadata.into_iter().map(owned).collect(),
+            metadata: self.metadata.into_owned(),
+            content: self.content.into_iter().map(Content::into_owned).collect(),
+            diagnostics: self.diagnostics,
+            trivia: self.trivia,
+        }
+    }
+}
+
+// Strings point into the input wherever the text appears there verbatim (which is most of
+// the time), and are only allocated when the parser had to build them. This detaches one
+fn owned(text: Cow<'_, str>) -> Cow<'static, str> {
+    Cow::Owned(text.into_owned())
+}
+
+// Trivia
+// Everything the writer needs to reproduce the source exactly: the raw lines (with their
+// line endings), what the writer itself makes of each of them, and where the header ended
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Trivia {
+    pub lines: Vec<String>,
+    pub(crate) canonical: Vec<Option<String>>,
+    pub header_end: Option<usize>,
+}
+
+// Diagnostics
+// Problems that don't stop the parse, but that someone should probably look at
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Diagnostic {
+    pub kind: DiagnosticKind,
+    pub span: Span,
+    pub message: String,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, EnumAsInner)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum DiagnosticKind {
+    MalformedPageTag,
+    UnrecognizedLine,
+    MetadataAfterHeader,
+    MalformedEntityPrefix,
+    MalformedEntityTag,
+    BadMetadataLine,
+}
+
+// Span
+// Where an item came from: 1-based line number, plus byte range within the whole input
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Span {
+    pub line: usize,
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    #[must_use]
+    pub const fn range(&self) -> Range<usize> {
+        self.start..self.end
+    }
 }
 
 // Content
 
-#[derive(Clone, Debug, EnumAsInner)]
-pub enum Content {
-    PageNumber(PageNumber),
+#[derive(Clone, Debug, PartialEq, Eq, EnumAsInner)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(tag = "type", rename_all = "snake_case"))]
+pub enum Content<'a> {
+    PageNumber(PageNumber<'a>),
     // Switched to use one para. variant, with field to indicate type
-    Paragraph { orig: String, para_type: ParaType },
-    Line(Line),
-    MorphologicalPattern { orig: String, category: String },
-    Editorial,
-    SectionHeader { value: String, level: u32 },
-    DictionaryUnit { orig: String, dic_type: DicType },
-    DoxographicalItem { orig: String, dox_type: DoxType },
-    BioOrEvent { orig: String, be_type: BeType },
+    Paragraph {
+        orig: Cow<'a, str>,
+        para_type: ParaType,
+        span: Span,
+    },
+    Line(Line<'a>),
+    MorphologicalPattern {
+        orig: Cow<'a, str>,
+        category: Cow<'a, str>,
+        span: Span,
+    },
+    // The whole line, so that the editor's note after the tag isn't lost
+    Editorial {
+        orig: Cow<'a, str>,
+        span: Span,
+    },
+    SectionHeader {
+        orig: Cow<'a, str>,
+        value: Cow<'a, str>,
+        level: u32,
+        span: Span,
+    },
+    DictionaryUnit {
+        orig: Cow<'a, str>,
+        dic_type: DicType,
+        span: Span,
+    },
+    DoxographicalItem {
+        orig: Cow<'a, str>,
+        dox_type: DoxType,
+        span: Span,
+    },
+    BioOrEvent {
+        orig: Cow<'a, str>,
+        be_type: BeType,
+        span: Span,
+    },
     // Admin. regions not yet fully implemented in Python library
-    AdministrativeRegion { orig: String },
+    AdministrativeRegion {
+        orig: Cow<'a, str>,
+        span: Span,
+    },
+    // Anything the parser doesn't recognize, kept so that nothing gets lost
+    Unknown {
+        orig: Cow<'a, str>,
+        span: Span,
+    },
+}
+
+impl Content<'_> {
+    #[must_use]
+    pub const fn span(&self) -> Span {
+        match self {
+            Self::PageNumber(PageNumber { span, .. })
+            | Self::Line(Line { span, .. })
+            | Self::Paragraph { span, .. }
+            | Self::MorphologicalPattern { span, .. }
+            | Self::Editorial { span, .. }
+            | Self::SectionHeader { span, .. }
+            | Self::DictionaryUnit { span, .. }
+            | Self::DoxographicalItem { span, .. }
+            | Self::BioOrEvent { span, .. }
+            | Self::AdministrativeRegion { span, .. }
+            | Self::Unknown { span, .. } => *span,
+        }
+    }
+
+    pub const fn span_mut(&mut self) -> &mut Span {
+        match self {
+            Self::PageNumber(PageNumber { span, .. })
+            | Self::Line(Line { span, .. })
+            | Self::Paragraph { span, .. }
+            | Self::MorphologicalPattern { span, .. }
+            | Self::Editorial { span, .. }
+            | Self::SectionHeader { span, .. }
+            | Self::DictionaryUnit { span, .. }
+            | Self::DoxographicalItem { span, .. }
+            | Self::BioOrEvent { span, .. }
+            | Self::AdministrativeRegion { span, .. }
+            | Self::Unknown { span, .. } => span,
+        }
+    }
+
+    #[must_use]
+    pub fn into_owned(self) -> Content<'static> {
+        match self {
+            Self::PageNumber(page_number) => Content::PageNumber(page_number.into_owned()),
+            Self::Paragraph {
+                orig,
+                para_type,
+                span,
+            } => Content::Paragraph {
+                orig: owned(orig),
+                para_type,
+                span,
+            },
+            Self::Line(line) => Content::Line(line.into_owned()),
+            Self::MorphologicalPattern {
+                orig,
+                category,
+                span,
+            } => Content::MorphologicalPattern {
+                orig: owned(orig),
+                category: owned(category),
+                span,
+            },
+            Self::Editorial { orig, span } => Content::Editorial {
+                orig: owned(orig),
+                span,
+            },
+            Self::SectionHeader {
+                orig,
+                value,
+                level,
+                span,
+            } => Content::SectionHeader {
+                orig: owned(orig),
+                value: owned(value),
+                level,
+                span,
+            },
+            Self::DictionaryUnit {
+                orig,
+                dic_type,
+                span,
+            } => Content::DictionaryUnit {
+                orig: owned(orig),
+                dic_type,
+                span,
+            },
+            Self::DoxographicalItem {
+                orig,
+                dox_type,
+                span,
+            } => Content::DoxographicalItem {
+                orig: owned(orig),
+                dox_type,
+                span,
+            },
+            Self::BioOrEvent {
+                orig,
+                be_type,
+                span,
+            } => Content::BioOrEvent {
+                orig: owned(orig),
+                be_type,
+                span,
+            },
+            Self::AdministrativeRegion { orig, span } => Content::AdministrativeRegion {
+                orig: owned(orig),
+                span,
+            },
+            Self::Unknown { orig, span } => Content::Unknown {
+                orig: owned(orig),
+                span,
+            },
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct PageNumber<'a> {
+    pub vol: Cow<'a, str>,
+    pub page: Cow<'a, str>,
+    pub span: Span,
 }
 
-#[derive(Clone, Debug)]
-pub struct PageNumber {
-    pub vol: String,
-    pub page: String,
+impl PageNumber<'_> {
+    #[must_use]
+    pub fn into_owned(self) -> PageNumber<'static> {
+        PageNumber {
+            vol: owned(self.vol),
+            page: owned(self.page),
+            span: self.span,
+        }
+    }
 }
 
-#[derive(Clone, Debug, EnumAsInner)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, EnumAsInner)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
 pub enum BeType {
     Man,
     Wom,
@@ -45,7 +294,9 @@ pub enum BeType {
     Events,
 }
 
-#[derive(Clone, Debug, EnumAsInner)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, EnumAsInner)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
 pub enum DicType {
     Nis,
     Top,
@@ -53,13 +304,17 @@ pub enum DicType {
     Bib,
 }
 
-#[derive(Clone, Debug, EnumAsInner)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, EnumAsInner)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
 pub enum DoxType {
     Pos,
     Sec,
 }
 
-#[derive(Clone, Debug, EnumAsInner)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, EnumAsInner)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
 pub enum ParaType {
     Normal,
     Riwayat,
@@ -68,14 +323,30 @@ pub enum ParaType {
 // Line
 // Here I switched to use one struct, with a field to indicate the type
 
-#[derive(Clone, Debug)]
-pub struct Line {
-    pub text_only: Option<String>,
-    pub parts: Vec<LinePart>,
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Line<'a> {
+    pub text_only: Option<Cow<'a, str>>,
+    pub parts: Vec<LinePart<'a>>,
     pub line_type: LineType,
+    pub span: Span,
 }
 
-#[derive(Clone, Debug, EnumAsInner)]
+impl Line<'_> {
+    #[must_use]
+    pub fn into_owned(self) -> Line<'static> {
+        Line {
+            text_only: self.text_only.map(owned),
+            parts: self.parts.into_iter().map(LinePart::into_owned).collect(),
+            line_type: self.line_type,
+            span: self.span,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, EnumAsInner)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
 pub enum LineType {
     Normal,
     RouteOrDistance,
@@ -85,60 +356,225 @@ pub enum LineType {
 // Line parts
 // PageNumber is a struct defined under Content; it can belong to either enum
 
-#[derive(Clone, Debug, EnumAsInner)]
-pub enum LinePart {
-    Isnad,
-    PageNumber(PageNumber),
+#[derive(Clone, Debug, PartialEq, Eq, EnumAsInner)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(tag = "type", rename_all = "snake_case"))]
+pub enum LinePart<'a> {
+    Isnad {
+        span: Span,
+    },
+    PageNumber(PageNumber<'a>),
     OpenTagUser {
-        user: String,
-        t_type: String,
-        t_subtype: String,
-        t_subsubtype: String,
+        user: Cow<'a, str>,
+        t_type: Cow<'a, str>,
+        t_subtype: Cow<'a, str>,
+        t_subsubtype: Cow<'a, str>,
+        span: Span,
     },
     OpenTagAuto {
-        resp: String,
-        t_type: String,
-        category: String,
-        review: String,
+        resp: Cow<'a, str>,
+        t_type: Cow<'a, str>,
+        category: Cow<'a, str>,
+        review: Cow<'a, str>,
+        span: Span,
     },
     Hemistich {
-        orig: String,
-    },
-    Milestone,
-    Matn,
-    Hukm,
-    RouteFrom,
-    RouteTowa,
-    RouteDist,
+        orig: Cow<'a, str>,
+        span: Span,
+    },
+    Milestone {
+        // The digits of a numbered milestone (ms0001); None for Milestone300
+        number: Option<Cow<'a, str>>,
+        span: Span,
+    },
+    Matn {
+        span: Span,
+    },
+    Hukm {
+        span: Span,
+    },
+    RouteFrom {
+        span: Span,
+    },
+    RouteTowa {
+        span: Span,
+    },
+    RouteDist {
+        span: Span,
+    },
     Date {
-        value: String,
+        value: Cow<'a, str>,
         date_type: DateType,
+        span: Span,
     },
     Age {
-        value: String,
+        value: Cow<'a, str>,
+        span: Span,
     },
     NamedEntity {
         prefix: u32,
         extent: u32,
         ne_type: EntityType,
+        span: Span,
     },
     TextPart {
-        text: String,
+        text: Cow<'a, str>,
+        span: Span,
     },
+    // The words a named-entity tag covers, less the prefix its first digit says to leave out,
+    // e.g., for @TOP11 وبغداد, prefix is و and text is بغداد. The span covers the text only;
+    // the prefix comes right before it
     NamedEntityText {
-        text: String,
+        prefix: Cow<'a, str>,
+        text: Cow<'a, str>,
         ne_type: EntityType,
+        span: Span,
     },
 }
 
-#[derive(Clone, Debug, EnumAsInner)]
+impl LinePart<'_> {
+    #[must_use]
+    pub const fn span(&self) -> Span {
+        match self {
+            Self::PageNumber(PageNumber { span, .. })
+            | Self::Isnad { span }
+            | Self::OpenTagUser { span, .. }
+            | Self::OpenTagAuto { span, .. }
+            | Self::Hemistich { span, .. }
+            | Self::Milestone { span, .. }
+            | Self::Matn { span }
+            | Self::Hukm { span }
+            | Self::RouteFrom { span }
+            | Self::RouteTowa { span }
+            | Self::RouteDist { span }
+            | Self::Date { span, .. }
+            | Self::Age { span, .. }
+            | Self::NamedEntity { span, .. }
+            | Self::TextPart { span, .. }
+            | Self::NamedEntityText { span, .. } => *span,
+        }
+    }
+
+    pub const fn span_mut(&mut self) -> &mut Span {
+        match self {
+            Self::PageNumber(PageNumber { span, .. })
+            | Self::Isnad { span }
+            | Self::OpenTagUser { span, .. }
+            | Self::OpenTagAuto { span, .. }
+            | Self::Hemistich { span, .. }
+            | Self::Milestone { span, .. }
+            | Self::Matn { span }
+            | Self::Hukm { span }
+            | Self::RouteFrom { span }
+            | Self::RouteTowa { span }
+            | Self::RouteDist { span }
+            | Self::Date { span, .. }
+            | Self::Age { span, .. }
+            | Self::NamedEntity { span, .. }
+            | Self::TextPart { span, .. }
+            | Self::NamedEntityText { span, .. } => span,
+        }
+    }
+
+    #[must_use]
+    pub fn into_owned(self) -> LinePart<'static> {
+        match self {
+            Self::Isnad { span } => LinePart::Isnad { span },
+            Self::PageNumber(page_number) => LinePart::PageNumber(page_number.into_owned()),
+            Self::OpenTagUser {
+                user,
+                t_type,
+                t_subtype,
+                t_subsubtype,
+                span,
+            } => LinePart::OpenTagUser {
+                user: owned(user),
+                t_type: owned(t_type),
+                t_subtype: owned(t_subtype),
+                t_subsubtype: owned(t_subsubtype),
+                span,
+            },
+            Self::OpenTagAuto {
+                resp,
+                t_type,
+                category,
+                review,
+                span,
+            } => LinePart::OpenTagAuto {
+                resp: owned(resp),
+                t_type: owned(t_type),
+                category: owned(category),
+                review: owned(review),
+                span,
+            },
+            Self::Hemistich { orig, span } => LinePart::Hemistich {
+                orig: owned(orig),
+                span,
+            },
+            Self::Milestone { number, span } => LinePart::Milestone {
+                number: number.map(owned),
+                span,
+            },
+            Self::Matn { span } => LinePart::Matn { span },
+            Self::Hukm { span } => LinePart::Hukm { span },
+            Self::RouteFrom { span } => LinePart::RouteFrom { span },
+            Self::RouteTowa { span } => LinePart::RouteTowa { span },
+            Self::RouteDist { span } => LinePart::RouteDist { span },
+            Self::Date {
+                value,
+                date_type,
+                span,
+            } => LinePart::Date {
+                value: owned(value),
+                date_type,
+                span,
+            },
+            Self::Age { value, span } => LinePart::Age {
+                value: owned(value),
+                span,
+            },
+            Self::NamedEntity {
+                prefix,
+                extent,
+                ne_type,
+                span,
+            } => LinePart::NamedEntity {
+                prefix,
+                extent,
+                ne_type,
+                span,
+            },
+            Self::TextPart { text, span } => LinePart::TextPart {
+                text: owned(text),
+                span,
+            },
+            Self::NamedEntityText {
+                prefix,
+                text,
+                ne_type,
+                span,
+            } => LinePart::NamedEntityText {
+                prefix: owned(prefix),
+                text: owned(text),
+                ne_type,
+                span,
+            },
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, EnumAsInner)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
 pub enum DateType {
     Birth,
     Death,
     Other,
 }
 
-#[derive(Clone, Debug, EnumAsInner)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, EnumAsInner)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
 pub enum EntityType {
     Top,
     Per,
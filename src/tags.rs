@@ -8,16 +8,14 @@ pub const LINE: &str = "~~";
 
 pub const HEMI: &str = "%~%";
 pub const MILESTONE: &str = "Milestone300";
+// Newer numbered milestones, e.g., ms0001
+pub const MILESTONE_NUMBERED: &str = "ms";
 pub const MATN: &str = "@MATN@";
 pub const HUKM: &str = "@HUKM@";
 pub const ROUTE_FROM: &str = "#$#FROM";
 pub const ROUTE_TOWA: &str = "#$#TOWA";
 pub const ROUTE_DIST: &str = "#$#DIST";
 
-pub const PHRASE_LV_TAGS: [&str; 7] = [
-    HEMI, MILESTONE, MATN, HUKM, ROUTE_FROM, ROUTE_TOWA, ROUTE_DIST,
-];
-
 pub const YEAR_BIRTH: &str = "@YB";
 pub const YEAR_DEATH: &str = "@YD";
 pub const YEAR_OTHER: &str = "@YY";
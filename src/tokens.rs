@@ -0,0 +1,31 @@
+// Arabic tokens
+// OpenITI counts text in tokens of Arabic script: runs of Arabic letters (including the
+// Persian ones), short vowels and other diacritics, tatweel, and Arabic-Indic digits.
+// Anything else (spaces, punctuation, Latin, tags) only separates tokens. Milestones, and
+// the word and character counts in version sidecars, are based on this
+
+#[must_use]
+pub const fn is_arabic_token_char(c: char) -> bool {
+    matches!(c,
+        '\u{0621}'..='\u{063A}'
+        | '\u{0640}'..='\u{0652}'
+        | '\u{0660}'..='\u{0669}'
+        | '\u{0670}'..='\u{06D3}'
+    )
+}
+
+pub fn arabic_tokens(text: &str) -> impl Iterator<Item = &str> {
+    text.split(|c| !is_arabic_token_char(c))
+        .filter(|token| !token.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tokens() {
+        let tokens: Vec<&str> = arabic_tokens("قالَ، ابنُ هشام: (213) x-y پیر ١٢٣").collect();
+        assert_eq!(tokens, ["قالَ", "ابنُ", "هشام", "پیر", "١٢٣"]);
+    }
+}
@@ -0,0 +1,352 @@
+use crate::error::UriError;
+use crate::metadata::Metadata;
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+// URIs
+// Every author, book, and version in OpenITI has a URI, built up one level at a time:
+// `0213IbnHisham`, then `0213IbnHisham.SiraNabawiyya`, then
+// `0213IbnHisham.SiraNabawiyya.Shamela0023833-ara1`. The header's BookURI has its own take
+// on it, with a dot after the date: `#0213.IbnHisham.SiraNabawiyya`
+
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Uri {
+    // Death date (AH) and short name of the author, e.g., 213 and `IbnHisham`
+    pub date: u32,
+    pub author: String,
+    pub book: Option<String>,
+    pub version: Option<VersionId>,
+}
+
+// The last part of a version URI, e.g., `Shamela0023833-ara1`
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct VersionId {
+    // Where the text came from, e.g., `Shamela0023833`
+    pub source: String,
+    // ISO 639-2 code, e.g., `ara`
+    pub language: String,
+    pub number: u32,
+}
+
+// The parts of a URI, for reporting which ones differ
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UriPart {
+    Date,
+    Author,
+    Book,
+    Version,
+}
+
+// Names are written in CamelCase with plain ASCII letters. Book titles may have digits too
+// (e.g., for a numbered part)
+fn is_name(text: &str, digits: bool) -> bool {
+    text.starts_with(|c: char| c.is_ascii_uppercase())
+        && text
+            .chars()
+            .all(|c| c.is_ascii_alphabetic() || (digits && c.is_ascii_digit()))
+}
+
+impl FromStr for VersionId {
+    type Err = UriError;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let bad = || UriError::BadVersion { text: text.into() };
+
+        let (source, suffix) = text.rsplit_once('-').ok_or_else(bad)?;
+
+        if source.is_empty() || !source.chars().all(|c| c.is_ascii_alphanumeric()) {
+            return Err(bad());
+        }
+
+        // Three lowercase letters, then the number
+        let language = suffix.get(..3).ok_or_else(bad)?;
+        let number = &suffix[3..];
+
+        if !language.chars().all(|c| c.is_ascii_lowercase())
+            || number.is_empty()
+            || !number.chars().all(|c| c.is_ascii_digit())
+        {
+            return Err(bad());
+        }
+
+        Ok(Self {
+            source: source.into(),
+            language: language.into(),
+            number: number.parse().map_err(|_| bad())?,
+        })
+    }
+}
+
+impl fmt::Display for VersionId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}-{}{}", self.source, self.language, self.number)
+    }
+}
+
+impl FromStr for Uri {
+    type Err = UriError;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let trimmed = text.trim().trim_start_matches('#');
+        let mut parts = trimmed.split('.');
+
+        // Whichever way the URI is written, it starts with four digits
+        let first = parts.next().unwrap_or_default();
+        let date = first
+            .get(..4)
+            .filter(|date| date.chars().all(|c| c.is_ascii_digit()))
+            .ok_or_else(|| UriError::BadDate { text: text.into() })?;
+
+        // Either `0213IbnHisham` or, from the header, `0213.IbnHisham`
+        let author = if first.len() == 4 {
+            parts.next().unwrap_or_default()
+        } else {
+            &first[4..]
+        };
+
+        if !is_name(author, false) {
+            return Err(UriError::BadAuthor {
+                text: author.into(),
+            });
+        }
+
+        let book = parts.next();
+        if let Some(book) = book {
+            if !is_name(book, true) {
+                return Err(UriError::BadBook { text: book.into() });
+            }
+        }
+
+        let version = parts.next().map(str::parse).transpose()?;
+
+        if parts.next().is_some() {
+            return Err(UriError::TooManyParts { text: text.into() });
+        }
+
+        Ok(Self {
+            date: date.parse().unwrap_or_default(),
+            author: author.into(),
+            book: book.map(Into::into),
+            version,
+        })
+    }
+}
+
+impl fmt::Display for Uri {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:04}{}", self.date, self.author)?;
+
+        if let Some(book) = &self.book {
+            write!(f, ".{book}")?;
+        }
+
+        if let Some(version) = &self.version {
+            write!(f, ".{version}")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Uri {
+    /// The URI of a text file, from its name. Extensions that mark how far along the text
+    /// is (`.mARkdown`, `.completed`, `.inProgress`) are ignored
+    ///
+    /// # Errors
+    ///
+    /// Will return an error if the file name isn't a valid URI.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Self, UriError> {
+        let name = path
+            .as_ref()
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or_default();
+
+        let name = ["mARkdown", "completed", "inProgress"]
+            .iter()
+            .find_map(|ext| name.strip_suffix(ext)?.strip_suffix('.'))
+            .unwrap_or(name);
+
+        name.parse()
+    }
+
+    /// The author part of the URI alone, e.g., `0213IbnHisham`
+    #[must_use]
+    pub fn author_uri(&self) -> Self {
+        Self {
+            book: None,
+            version: None,
+            ..self.clone()
+        }
+    }
+
+    /// The book part of the URI, e.g., `0213IbnHisham.SiraNabawiyya`. None for an author URI
+    #[must_use]
+    pub fn book_uri(&self) -> Option<Self> {
+        self.book.as_ref()?;
+
+        Some(Self {
+            version: None,
+            ..self.clone()
+        })
+    }
+
+    /// Which parts differ between two URIs. Parts that only one of them has (e.g., the
+    /// version, when comparing the header's BookURI to a file name) are not counted
+    #[must_use]
+    pub fn mismatches(&self, other: &Self) -> Vec<UriPart> {
+        let mut parts = Vec::new();
+
+        if self.date != other.date {
+            parts.push(UriPart::Date);
+        }
+
+        if self.author != other.author {
+            parts.push(UriPart::Author);
+        }
+
+        if let (Some(book), Some(other_book)) = (&self.book, &other.book) {
+            if book != other_book {
+                parts.push(UriPart::Book);
+            }
+        }
+
+        if let (Some(version), Some(other_version)) = (&self.version, &other.version) {
+            if version != other_version {
+                parts.push(UriPart::Version);
+            }
+        }
+
+        parts
+    }
+}
+
+impl Metadata<'_> {
+    /// The header's BookURI, parsed. None if there isn't one
+    #[must_use]
+    pub fn uri(&self) -> Option<Result<Uri, UriError>> {
+        self.book_uri().map(str::parse)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let uri: Uri = "0213IbnHisham.SiraNabawiyya.Shamela0023833-ara1"
+            .parse()
+            .unwrap();
+
+        assert_eq!(uri.date, 213);
+        assert_eq!(uri.author, "IbnHisham");
+        assert_eq!(uri.book.as_deref(), Some("SiraNabawiyya"));
+        assert_eq!(
+            uri.version,
+            Some(VersionId {
+                source: "Shamela0023833".into(),
+                language: "ara".into(),
+                number: 1,
+            })
+        );
+        assert_eq!(
+            uri.to_string(),
+            "0213IbnHisham.SiraNabawiyya.Shamela0023833-ara1"
+        );
+
+        assert_eq!(
+            uri.book_uri().unwrap().to_string(),
+            "0213IbnHisham.SiraNabawiyya"
+        );
+        assert_eq!(uri.author_uri().to_string(), "0213IbnHisham");
+        assert_eq!(uri.author_uri().book_uri(), None);
+
+        // The header's way of writing it
+        let header: Uri = "#0213.IbnHisham.SiraNabawiyya".parse().unwrap();
+        assert_eq!(header, uri.book_uri().unwrap());
+    }
+
+    #[test]
+    fn invalid() {
+        let cases = [
+            (
+                "213IbnHisham",
+                UriError::BadDate {
+                    text: "213IbnHisham".into(),
+                },
+            ),
+            (
+                "0213",
+                UriError::BadAuthor {
+                    text: String::new(),
+                },
+            ),
+            (
+                "0213ibnHisham",
+                UriError::BadAuthor {
+                    text: "ibnHisham".into(),
+                },
+            ),
+            (
+                "0213Ibn_Hisham",
+                UriError::BadAuthor {
+                    text: "Ibn_Hisham".into(),
+                },
+            ),
+            (
+                "0213IbnHisham.Sira Nabawiyya",
+                UriError::BadBook {
+                    text: "Sira Nabawiyya".into(),
+                },
+            ),
+            (
+                "0213IbnHisham.SiraNabawiyya.Shamela0023833",
+                UriError::BadVersion {
+                    text: "Shamela0023833".into(),
+                },
+            ),
+            (
+                "0213IbnHisham.SiraNabawiyya.Shamela0023833-ARA1",
+                UriError::BadVersion {
+                    text: "Shamela0023833-ARA1".into(),
+                },
+            ),
+            (
+                "0213IbnHisham.SiraNabawiyya.Shamela0023833-ara",
+                UriError::BadVersion {
+                    text: "Shamela0023833-ara".into(),
+                },
+            ),
+            (
+                "0213IbnHisham.SiraNabawiyya.Shamela0023833-ara1.txt",
+                UriError::TooManyParts {
+                    text: "0213IbnHisham.SiraNabawiyya.Shamela0023833-ara1.txt".into(),
+                },
+            ),
+        ];
+
+        for (text, err) in cases {
+            assert_eq!(text.parse::<Uri>(), Err(err), "{text}");
+        }
+    }
+
+    #[test]
+    fn compare() {
+        let path = Uri::from_path(
+            "data/0213IbnHisham/0213IbnHisham.SiraNabawiyya/0213IbnHisham.SiraNabawiyya.Shamela0023833-ara1.completed",
+        )
+        .unwrap();
+        assert_eq!(path.version.as_ref().unwrap().source, "Shamela0023833");
+
+        let full_text = std::fs::read_to_string("test.md").unwrap();
+        let doc = crate::parser(&full_text).unwrap();
+        let header = doc.metadata.uri().unwrap().unwrap();
+
+        assert!(header.mismatches(&path).is_empty());
+
+        let other: Uri = "0150IbnJurayj.SiraNabawiyya".parse().unwrap();
+        assert_eq!(header.mismatches(&other), [UriPart::Date, UriPart::Author]);
+    }
+}
@@ -0,0 +1,655 @@
+use crate::structures::*;
+use crate::tags::*;
+
+// The writer goes the other way: from a Document back to mARkdown. Block markers and inline
+// tags are regenerated from the parsed types, so edits to the tree show up in the output.
+// Where the source had a choice of spelling (e.g., "### $" vs. "### $BIO_MAN$"), we reuse
+// the one found in the item's orig field, as long as it still reads back as the same type
+
+//
+// INLINE PARTS
+//
+
+fn write_part(part: &LinePart) -> String {
+    match part {
+        // The isnād marker comes from the riwāya tag at the start of the line
+        LinePart::Isnad { .. } => String::new(),
+        LinePart::PageNumber(page_number) => write_page_number(page_number),
+        LinePart::OpenTagUser {
+            user,
+            t_type,
+            t_subtype,
+            t_subsubtype,
+            ..
+        } => {
+            if t_subsubtype.is_empty() {
+                format!("@{user}@{t_type}_{t_subtype}@")
+            } else {
+                format!("@{user}@{t_type}_{t_subtype}_{t_subsubtype}@")
+            }
+        }
+        LinePart::OpenTagAuto {
+            resp,
+            t_type,
+            category,
+            review,
+            ..
+        } => {
+            if review.is_empty() {
+                format!("@{resp}@{t_type}@{category}@")
+            } else {
+                format!("@{resp}@{t_type}@{category}@-@{review}@")
+            }
+        }
+        LinePart::Hemistich { orig, .. } => orig.to_string(),
+        LinePart::Milestone { number, .. } => match number {
+            Some(number) => format!("{MILESTONE_NUMBERED}{number}"),
+            None => MILESTONE.into(),
+        },
+        LinePart::Matn { .. } => MATN.into(),
+        LinePart::Hukm { .. } => HUKM.into(),
+        LinePart::RouteFrom { .. } => ROUTE_FROM.into(),
+        LinePart::RouteTowa { .. } => ROUTE_TOWA.into(),
+        LinePart::RouteDist { .. } => ROUTE_DIST.into(),
+        LinePart::Date {
+            value, date_type, ..
+        } => match date_type {
+            DateType::Birth => format!("{YEAR_BIRTH}{value}"),
+            DateType::Death => format!("{YEAR_DEATH}{value}"),
+            DateType::Other => format!("{YEAR_OTHER}{value}"),
+        },
+        LinePart::Age { value, .. } => format!("{YEAR_AGE}{value}"),
+        LinePart::NamedEntity {
+            prefix,
+            extent,
+            ne_type,
+            ..
+        } => format!("{}{prefix}{extent}", entity_tag(*ne_type)),
+        LinePart::TextPart { text, .. } => text.to_string(),
+        LinePart::NamedEntityText { prefix, text, .. } => format!("{prefix}{text}"),
+    }
+}
+
+// Always the long form of the tag, e.g., @PER rather than @P
+pub const fn entity_tag(ne_type: EntityType) -> &'static str {
+    match ne_type {
+        EntityType::Top => TOP_FULL,
+        EntityType::Per => PER_FULL,
+        EntityType::Soc => SOC_FULL,
+        EntityType::Src => SRC,
+    }
+}
+
+fn write_page_number(page_number: &PageNumber) -> String {
+    format!("{PAGE}{}P{}", page_number.vol, page_number.page)
+}
+
+// Parts are separated by single spaces, unless their spans show that they were written
+// together in the source (e.g., "Milestone300،")
+fn write_parts(line: &Line) -> String {
+    let mut output = String::new();
+    let mut previous: Option<Span> = None;
+
+    for part in &line.parts {
+        let written = write_part(part);
+        if written.is_empty() {
+            continue;
+        }
+
+        let span = part.span();
+        let adjacent = previous.is_some_and(|prev| prev.line > 0 && prev.end == span.start);
+
+        if previous.is_some() && !adjacent {
+            output.push(' ');
+        }
+
+        output.push_str(&written);
+        previous = Some(span);
+    }
+
+    output
+}
+
+//
+// BLOCK MARKERS
+//
+
+// The longest of the given tags that the original line started with, if any
+fn tag_from_orig<'a>(orig: &str, tags: &[&'a str]) -> Option<&'a str> {
+    tags.iter()
+        .filter(|tag| orig.starts_with(**tag))
+        .max_by_key(|tag| tag.len())
+        .copied()
+}
+
+fn bio_marker(orig: &str, be_type: BeType) -> &'static str {
+    if let Some(tag) = tag_from_orig(orig, &BIOS_EVENTS) {
+        if crate::be_type(tag) == be_type {
+            return tag;
+        }
+    }
+
+    // Note that the parser doesn't read EVENT_FULL as an event, so we avoid it here
+    match be_type {
+        BeType::Man => BIO_MAN_FULL,
+        BeType::Wom => BIO_WOM_FULL,
+        BeType::Ref => BIO_REF_FULL,
+        BeType::Names => LIST_NAMES_FULL,
+        BeType::Event => EVENT,
+        BeType::Events => LIST_EVENTS,
+    }
+}
+
+fn dic_marker(orig: &str, dic_type: DicType) -> &'static str {
+    if let Some(tag) = tag_from_orig(orig, &DICTIONARIES) {
+        if crate::dic_type(tag) == dic_type {
+            return tag;
+        }
+    }
+
+    match dic_type {
+        DicType::Nis => DIC_NIS,
+        DicType::Top => DIC_TOP,
+        DicType::Lex => DIC_LEX,
+        DicType::Bib => DIC_BIB,
+    }
+}
+
+fn dox_marker(orig: &str, dox_type: DoxType) -> &'static str {
+    if let Some(tag) = tag_from_orig(orig, &DOXOGRAPHICAL) {
+        if crate::dox_type(tag) == dox_type {
+            return tag;
+        }
+    }
+
+    match dox_type {
+        DoxType::Pos => DOX_POS,
+        DoxType::Sec => DOX_SEC,
+    }
+}
+
+const fn para_marker(para_type: ParaType) -> &'static str {
+    match para_type {
+        ParaType::Normal => "#",
+        ParaType::Riwayat => RWY,
+    }
+}
+
+fn header_marker(level: u32) -> String {
+    let level = level.clamp(1, 5) as usize;
+    format!("### {}", "|".repeat(level))
+}
+
+// Standalone lines get their marker from the line type
+fn write_line(line: &Line) -> String {
+    let text = write_parts(line);
+
+    match line.line_type {
+        LineType::Normal => format!("{LINE}{text}"),
+        LineType::Verse => format!("# {text}"),
+        LineType::RouteOrDistance => text,
+    }
+}
+
+// A marker followed by the first line of its block, if that line is on the same physical line
+fn with_first_line(marker: &str, first_line: Option<&Line>) -> String {
+    match first_line {
+        Some(line) => format!("{marker} {}", write_parts(line)),
+        None => marker.into(),
+    }
+}
+
+//
+// LINE PLAN
+//
+
+// Where an output line comes from. This only matters in lossless mode, where lines that
+// haven't changed are copied from the source instead
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Origin {
+    Source(usize),
+    New,
+}
+
+const fn origin_of(span: Span) -> Origin {
+    if span.line > 0 {
+        Origin::Source(span.line)
+    } else {
+        Origin::New
+    }
+}
+
+// The source lines that the parser took as metadata, in order: everything after the magic
+// value that starts with #META#, apart from the end of the header
+fn metadata_lines(trivia: &Trivia) -> Vec<usize> {
+    trivia
+        .lines
+        .iter()
+        .enumerate()
+        .skip(1)
+        .filter(|(_, line)| {
+            let line = line.trim();
+            line.starts_with(META) && line != META_END
+        })
+        .map(|(i, _)| i + 1)
+        .collect()
+}
+
+// The metadata and the end of the header
+fn plan_header(doc: &Document) -> Vec<(Origin, String)> {
+    let mut lines: Vec<(Origin, String)> = Vec::new();
+
+    // Not every metadata line has a structured entry (with a span), so they're matched up
+    // with the source lines instead, unless some have been added or taken away since
+    let sources = doc
+        .trivia
+        .as_ref()
+        .map(metadata_lines)
+        .filter(|sources| sources.len() == doc.simple_metadata.len());
+
+    for (i, entry) in doc.simple_metadata.iter().enumerate() {
+        let origin = sources
+            .as_ref()
+            .map_or(Origin::New, |sources| Origin::Source(sources[i]));
+
+        lines.push((origin, format!("{META} {entry}")));
+    }
+
+    // The end of the header is only written if the source had one, or (when we don't know)
+    // if there's a header to end
+    let end = match &doc.trivia {
+        Some(trivia) => trivia.header_end.map(Origin::Source),
+        None => (!doc.simple_metadata.is_empty()).then_some(Origin::New),
+    };
+    // Stray metadata after it goes after it
+    if let Some(origin) = end {
+        let at = lines
+            .iter()
+            .position(|(other, _)| matches!((other, origin), (Origin::Source(other), Origin::Source(end)) if *other > end))
+            .unwrap_or(lines.len());
+        lines.insert(at, (origin, META_END.into()));
+    }
+
+    lines
+}
+
+fn plan_content(doc: &Document) -> Vec<(Origin, String)> {
+    let mut lines: Vec<(Origin, String)> = Vec::new();
+    let mut items = doc.content.iter().peekable();
+
+    while let Some(item) = items.next() {
+        // The parser emits a block marker followed by a Line for whatever came after the
+        // marker on the same physical line. We need to put those back together
+        let mut first_line = || {
+            let line = items.next_if(
+                |next| matches!(next, Content::Line(line) if line.span.line == item.span().line),
+            )?;
+            line.as_line()
+        };
+
+        let written = match item {
+            Content::PageNumber(page_number) => write_page_number(page_number),
+            Content::Paragraph { para_type, .. } => {
+                with_first_line(para_marker(*para_type), first_line())
+            }
+            Content::Line(line) => write_line(line),
+            Content::MorphologicalPattern { orig, .. }
+            | Content::AdministrativeRegion { orig, .. }
+            | Content::Unknown { orig, .. } => orig.to_string(),
+            Content::Editorial { orig, .. } => {
+                if orig.starts_with(EDITORIAL) {
+                    orig.to_string()
+                } else {
+                    EDITORIAL.into()
+                }
+            }
+            // Inline tags in headers only survive in orig, so we keep it if we can
+            Content::SectionHeader {
+                orig, value, level, ..
+            } => {
+                if orig.starts_with(HEADER1)
+                    && crate::header_level(orig) == *level
+                    && crate::header_value(orig) == *value
+                {
+                    orig.to_string()
+                } else {
+                    format!("{} {value}", header_marker(*level))
+                }
+            }
+            Content::DictionaryUnit { orig, dic_type, .. } => {
+                with_first_line(dic_marker(orig, *dic_type), first_line())
+            }
+            Content::DoxographicalItem { orig, dox_type, .. } => {
+                with_first_line(dox_marker(orig, *dox_type), first_line())
+            }
+            Content::BioOrEvent { orig, be_type, .. } => {
+                with_first_line(bio_marker(orig, *be_type), first_line())
+            }
+        };
+
+        lines.push((origin_of(item.span()), written));
+    }
+
+    lines
+}
+
+fn plan(doc: &Document) -> Vec<(Origin, String)> {
+    let mut lines = vec![(Origin::Source(1), doc.magic_value.to_string())];
+    let header = plan_header(doc);
+    let content = plan_content(doc);
+
+    if doc.trivia.is_none() {
+        lines.extend(header);
+        lines.extend(content);
+        return lines;
+    }
+
+    // In lossless mode, header lines go back in among the content wherever they were in the
+    // source (there can be text before the end of the header, or stray metadata after it).
+    // The content itself stays in the order it's in, edits and all
+    let mut header = header.into_iter().peekable();
+
+    for (origin, text) in content {
+        if let Origin::Source(line) = origin {
+            while let Some(next) = header.next_if(|(next, _)| match next {
+                Origin::Source(header_line) => *header_line < line,
+                Origin::New => true,
+            }) {
+                lines.push(next);
+            }
+        }
+
+        lines.push((origin, text));
+    }
+
+    lines.extend(header);
+    lines
+}
+
+// What the writer makes of each source line, recorded by the parser in lossless mode.
+// Lines that the writer doesn't produce at all (blank lines, etc.) get None
+pub fn canonical_lines(doc: &Document, line_count: usize) -> Vec<Option<String>> {
+    let mut canonical = vec![None; line_count];
+
+    for (origin, text) in plan(doc) {
+        if let Origin::Source(line) = origin {
+            if let Some(slot) = canonical.get_mut(line - 1) {
+                *slot = Some(text);
+            }
+        }
+    }
+
+    canonical
+}
+
+fn line_ending(line: &str) -> &str {
+    if line.ends_with("\r\n") {
+        "\r\n"
+    } else if line.ends_with('\n') {
+        "\n"
+    } else {
+        ""
+    }
+}
+
+// Any line whose rendering is the same as when it was parsed is copied from the source,
+// along with the blank lines and other content-less lines around it. Everything else
+// (new or edited items) is written out as usual
+fn write_lossless(lines: Vec<(Origin, String)>, trivia: &Trivia) -> String {
+    let mut output = String::new();
+    let mut cursor = 0;
+
+    // New lines get whatever line ending the source has been using
+    let mut ending = "\n";
+
+    // Copy over content-less source lines, up to (but not including) the given index.
+    // Lines that did have content and aren't in the plan anymore have been deleted
+    let flush = |output: &mut String, cursor: &mut usize, until: usize| {
+        while *cursor < until.min(trivia.lines.len()) {
+            if trivia.canonical.get(*cursor).is_none_or(Option::is_none) {
+                output.push_str(&trivia.lines[*cursor]);
+            }
+
+            *cursor += 1;
+        }
+    };
+
+    for (origin, text) in lines {
+        let index = match origin {
+            Origin::Source(line) => line - 1,
+            Origin::New => {
+                // The last line of the source may not have had a line ending
+                if !output.is_empty() && !output.ends_with('\n') {
+                    output.push_str(ending);
+                }
+
+                output.push_str(&text);
+                output.push_str(ending);
+                continue;
+            }
+        };
+
+        flush(&mut output, &mut cursor, index);
+
+        if let Some(source) = trivia.lines.get(index) {
+            let unchanged = trivia.canonical.get(index).and_then(Option::as_deref) == Some(&text);
+
+            if unchanged {
+                output.push_str(source);
+            } else {
+                output.push_str(&text);
+                output.push_str(line_ending(source));
+            }
+
+            if !line_ending(source).is_empty() {
+                ending = line_ending(source);
+            }
+        } else {
+            output.push_str(&text);
+            output.push_str(ending);
+        }
+
+        cursor = cursor.max(index + 1);
+    }
+
+    flush(&mut output, &mut cursor, trivia.lines.len());
+
+    output
+}
+
+//
+// MAIN WRITER FUNCTION
+//
+
+/// Serialize a [`Document`] back to OpenITI mARkdown.
+///
+/// The header is written from `simple_metadata`, so that placeholders like `NOTGIVEN` come
+/// back as they were, and left out if there isn't any. Diagnostics are not written.
+///
+/// If the document was parsed in lossless mode (see [`ParseOptions`](crate::ParseOptions)),
+/// every line that hasn't been changed since is reproduced exactly as it was in the source.
+#[must_use]
+pub fn write(doc: &Document) -> String {
+    let lines = plan(doc);
+
+    if let Some(trivia) = &doc.trivia {
+        return write_lossless(lines, trivia);
+    }
+
+    let lines: Vec<String> = lines.into_iter().map(|(_, text)| text).collect();
+
+    let mut output = lines.join("\n");
+    output.push('\n');
+
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parser, parser_with_options, tests::markup_line, ParseOptions};
+    use proptest::prelude::*;
+    use std::fs;
+
+    // Spans will obviously differ between the original and the rewritten text
+    fn without_spans(mut doc: Document) -> Document {
+        for item in &mut doc.content {
+            *item.span_mut() = Span::default();
+
+            if let Content::Line(line) = item {
+                for part in &mut line.parts {
+                    *part.span_mut() = Span::default();
+                }
+            }
+        }
+
+        for entry in &mut doc.metadata.entries {
+            entry.span = Span::default();
+        }
+
+        for diagnostic in &mut doc.diagnostics {
+            diagnostic.span = Span::default();
+        }
+
+        doc
+    }
+
+    fn round_trip(full_text: &str) {
+        let parsed = parser(full_text).unwrap();
+        let written = write(&parsed);
+        let reparsed = without_spans(parser(&written).unwrap());
+        let parsed = without_spans(parsed);
+
+        // Item by item first, so that a failure is readable
+        for (a, b) in parsed.content.iter().zip(&reparsed.content) {
+            assert_eq!(a, b);
+        }
+
+        assert_eq!(parsed, reparsed);
+    }
+
+    #[test]
+    fn round_trip_files() {
+        for path in ["test.md", "test2.md"] {
+            round_trip(&fs::read_to_string(path).unwrap());
+        }
+    }
+
+    #[test]
+    fn round_trip_odd_lines() {
+        // Lines with nothing but tags, or nothing at all, and editorial notes
+        round_trip("######OpenITI#\n#META# 000.SortField :: JK_011476\n#META#Header#End#\n# @MATN@\n~~ Milestone300\n~~\n#\n### |EDITOR| ينظر الأصل\n~~@YD213\n");
+
+        // Nothing is added that the source didn't have: no header, no blank lines
+        let input = "######OpenITI#\n# @MATN@ نص\n### |EDITOR| ينظر الأصل\n~~ Milestone300\n";
+        round_trip(input);
+        assert_eq!(
+            write(&parser(input).unwrap()),
+            "######OpenITI#\n# @MATN@ نص\n### |EDITOR| ينظر الأصل\n~~Milestone300\n"
+        );
+    }
+
+    #[test]
+    fn markers() {
+        let input = "######OpenITI#\n#META#Header#End#\n### $ أبو عمرو\n### $BIO_WOM$ صمعة\n### @ RAW\n### ||| عنوان\n# $RWY$ حدثنا @MATN@ قال\n~~@PER02 عبد الملك @YD213 PageV01P002";
+        let written = write(&parser(input).unwrap());
+
+        assert_eq!(
+            written,
+            "######OpenITI#\n### $ أبو عمرو\n### $BIO_WOM$ صمعة\n### @ RAW\n### ||| عنوان\n# $RWY$ حدثنا @MATN@ قال\n~~@PER02 عبد الملك @YD213 PageV01P002\n"
+        );
+
+        // Edited types get their canonical marker
+        let mut doc = parser(input).unwrap();
+        doc.content[0] = Content::BioOrEvent {
+            orig: "### $ أبو عمرو".into(),
+            be_type: BeType::Ref,
+            span: doc.content[0].span(),
+        };
+
+        assert!(write(&doc).contains("\n### $BIO_REF$ أبو عمرو\n"));
+    }
+
+    fn lossless(input: &str) -> Document<'_> {
+        parser_with_options(
+            input,
+            ParseOptions {
+                lossless: true,
+                ..Default::default()
+            },
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn lossless_files() {
+        for path in ["test.md", "test2.md"] {
+            let full_text = fs::read_to_string(path).unwrap();
+            assert_eq!(write(&lossless(&full_text)), full_text);
+        }
+    }
+
+    #[test]
+    fn lossless_trivia() {
+        let input = "######OpenITI#\r\n\r\n#META# 000.SortField  :: JK_011476\r\n#META#Header#End#\r\n\r\n\r\n  ### $ أبو   عمرو  \r\n~~ زبان  @PER02 بن العلاء\tPageV01P002\r\n~~\r\n#META# 999.MiscINFO :: NODATA\r\nstray";
+        let doc = lossless(input);
+
+        assert_eq!(write(&doc), input);
+
+        // Content before the end of the header, and metadata in among it
+        for input in [
+            "######OpenITI#\n# a\n#META#Header#End#\n",
+            "######OpenITI#\n# نص\n#META# 000.SortField :: JK_011476\n~~ بقية\n#META#Header#End#\n### | باب\n",
+        ] {
+            assert_eq!(write(&lossless(input)), input);
+        }
+
+        // A metadata line that isn't a key-value pair stays where it was
+        let input = "######OpenITI#\n#META# no separator\n#META# 000.SortField :: JK_011476\n#META#Header#End#\n# نص\n";
+        assert_eq!(write(&lossless(input)), input);
+    }
+
+    proptest! {
+        #[test]
+        fn lossless_generated(lines in prop::collection::vec(markup_line(), 0..12)) {
+            let input = format!("######OpenITI#\n{}", lines.join("\n"));
+            prop_assert_eq!(write(&lossless(&input)), input);
+        }
+    }
+
+    #[test]
+    fn lossless_edits() {
+        let full_text = fs::read_to_string("test.md").unwrap();
+        let mut doc = lossless(&full_text);
+
+        // Change one word, drop one item, and add one
+        if let Content::Line(line) = &mut doc.content[3] {
+            if let LinePart::TextPart { text, .. } = &mut line.parts[0] {
+                *text = "أبو عمرو بن العلاء".into();
+            }
+        }
+
+        doc.content.drain(8..10);
+        doc.content.insert(
+            8,
+            Content::SectionHeader {
+                orig: "".into(),
+                value: "عنوان".into(),
+                level: 2,
+                span: Span::default(),
+            },
+        );
+
+        let written = write(&doc);
+        let before: Vec<&str> = full_text.lines().collect();
+        let after: Vec<&str> = written.lines().collect();
+
+        assert_eq!(after.len(), before.len());
+        assert_eq!(after[40], "### $BIO_MAN$ أبو عمرو بن العلاء");
+        assert_eq!(after[45], "### || عنوان");
+
+        let changed = before.iter().zip(&after).filter(|(a, b)| a != b).count();
+        assert_eq!(changed, 2);
+    }
+}